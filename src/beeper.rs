@@ -0,0 +1,171 @@
+use sdl2::audio::AudioCallback;
+
+use std::f32::consts::TAU;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::config::Config;
+
+pub const SAMPLE_RATE: i32 = 44100;
+
+pub const MIN_FREQUENCY: f32 = 40.0;
+pub const MAX_FREQUENCY: f32 = 2000.0;
+
+// How long the sound takes to fade in or out. Starting or stopping a wave instantly makes an audible click
+const ENVELOPE_SECONDS: f32 = 0.005;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+    Noise,
+}
+
+impl Waveform {
+    pub fn next(self) -> Self {
+        match self {
+            Waveform::Square => Waveform::Sine,
+            Waveform::Sine => Waveform::Triangle,
+            Waveform::Triangle => Waveform::Noise,
+            Waveform::Noise => Waveform::Square,
+        }
+    }
+}
+
+impl fmt::Display for Waveform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Waveform::Square => write!(f, "square"),
+            Waveform::Sine => write!(f, "sine"),
+            Waveform::Triangle => write!(f, "triangle"),
+            Waveform::Noise => write!(f, "noise"),
+        }
+    }
+}
+
+impl FromStr for Waveform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "square" => Ok(Waveform::Square),
+            "sine" => Ok(Waveform::Sine),
+            "triangle" => Ok(Waveform::Triangle),
+            "noise" => Ok(Waveform::Noise),
+            _ => Err(format!("\"{}\" is not a waveform, expected square, sine, triangle or noise", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct BeeperSettings {
+    pub waveform: Waveform,
+    // For noise this is how often a new random level is picked, which gives it a pitch of sorts
+    pub frequency: f32,
+    // From 0 to 1
+    pub volume: f32,
+}
+
+impl BeeperSettings {
+    pub fn from_config(config: &Config) -> Self {
+        BeeperSettings {
+            waveform: config.get_or_warn("beep_waveform").unwrap_or(Waveform::Square),
+            frequency: config.get_or_warn("beep_frequency").unwrap_or(440.0f32).clamp(MIN_FREQUENCY, MAX_FREQUENCY),
+            volume: config.get_or_warn("beep_volume").unwrap_or(0.25f32).clamp(0.0, 1.0),
+        }
+    }
+
+    // Writes the settings back to the config file they are read from
+    pub fn store(&self, path: &Path) -> Result<(), String> {
+        Config::store(path, "beep_waveform", &self.waveform.to_string())?;
+        Config::store(path, "beep_frequency", &format!("{:.0}", self.frequency))?;
+        Config::store(path, "beep_volume", &format!("{:.2}", self.volume))
+    }
+}
+
+// Generates the sound of the CHIP-8's beeper. It is always running, and is switched on and off with
+// set_gate, fading in and out rather than cutting off
+pub struct Beeper {
+    pub settings: BeeperSettings,
+    sample_rate: f32,
+    phase: f32,
+    gate: bool,
+    level: f32,
+    noise_state: u32,
+    noise_sample: f32,
+}
+
+impl Beeper {
+    pub fn new(settings: BeeperSettings, sample_rate: i32) -> Self {
+        Beeper {
+            settings,
+            sample_rate: sample_rate as f32,
+            phase: 0.0,
+            gate: false,
+            level: 0.0,
+            noise_state: 0x1234_5678,
+            noise_sample: 0.0,
+        }
+    }
+
+    pub fn set_gate(&mut self, on: bool) {
+        self.gate = on;
+    }
+
+    pub fn next_sample(&mut self) -> f32 {
+        let step = 1.0 / (ENVELOPE_SECONDS * self.sample_rate);
+        let target = if self.gate { 1.0 } else { 0.0 };
+
+        self.level = if self.level < target { (self.level + step).min(target) } else { (self.level - step).max(target) };
+
+        // Goes back to the start of the wave while silent, so the next beep starts cleanly from the beginning of a cycle
+        if self.level == 0.0 {
+            self.phase = 0.0;
+            return 0.0;
+        }
+
+        let wave = match self.settings.waveform {
+            Waveform::Square => if self.phase < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Sine => (self.phase * TAU).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (self.phase - 0.5).abs(),
+            Waveform::Noise => {
+                if self.phase == 0.0 {
+                    self.next_noise();
+                }
+
+                self.noise_sample
+            },
+        };
+
+        let next_phase = self.phase + self.settings.frequency / self.sample_rate;
+
+        if self.settings.waveform == Waveform::Noise && next_phase >= 1.0 {
+            self.next_noise();
+        }
+
+        self.phase = next_phase % 1.0;
+
+        wave * self.settings.volume * self.level
+    }
+
+    // xorshift32, as the noise only has to sound random
+    fn next_noise(&mut self) {
+        self.noise_state ^= self.noise_state << 13;
+        self.noise_state ^= self.noise_state >> 17;
+        self.noise_state ^= self.noise_state << 5;
+
+        self.noise_sample = (self.noise_state as f32 / u32::MAX as f32) * 2.0 - 1.0;
+    }
+}
+
+impl AudioCallback for Beeper {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [Self::Channel]) {
+        for x in out.iter_mut() {
+            *x = self.next_sample();
+        }
+    }
+}
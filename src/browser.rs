@@ -0,0 +1,262 @@
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::loader::{self, RomChoice};
+use crate::recent::RecentRoms;
+use crate::text::{self, CELL_HEIGHT};
+
+pub const DEFAULT_ROM_FOLDER: &str = "roms";
+
+// Each folder can have one of these, made of "file name = title" lines, to show nicer names than the file names
+const TITLES_FILE: &str = "titles.cfg";
+
+const TEXT_SCALE: u32 = 3;
+const MARGIN: i32 = 16;
+const ROW_HEIGHT: i32 = ((CELL_HEIGHT + 2) * TEXT_SCALE) as i32;
+const LIST_TOP: i32 = MARGIN + ROW_HEIGHT * 2;
+
+const BACKGROUND: Color = Color::RGB(16, 16, 16);
+const TEXT: Color = Color::RGB(220, 220, 220);
+const DIM_TEXT: Color = Color::RGB(120, 120, 120);
+const HIGHLIGHT: Color = Color::RGB(60, 60, 120);
+
+struct BrowserEntry {
+    rom: RomChoice,
+    title: String,
+}
+
+pub enum BrowserAction {
+    None,
+    Load(RomChoice),
+    // Closes the browser, going back to whatever was running
+    Cancel,
+    // The window was closed
+    Quit,
+}
+
+pub struct RomBrowser {
+    heading: String,
+    entries: Vec<BrowserEntry>,
+    selected: usize,
+    scroll: usize,
+}
+
+impl RomBrowser {
+    // Lists the recently played ROMs followed by every ROM found in the given folders and their subfolders.
+    // Archives holding more than one ROM are listed once per ROM
+    pub fn for_folders(folders: &[String], recent: &RecentRoms) -> Self {
+        let mut entries = Vec::new();
+
+        for rom in recent.roms() {
+            entries.push(BrowserEntry {
+                title: format!("Recent: {}", title_for(rom)),
+                rom: rom.clone(),
+            });
+        }
+
+        for folder in folders {
+            let mut files = Vec::new();
+            find_roms(Path::new(folder), &mut files);
+            files.sort();
+
+            for file in files {
+                let path = file.to_string_lossy().to_string();
+
+                if loader::is_archive(&path) {
+                    match loader::archive_roms(&path) {
+                        Ok(names) if names.len() > 1 => {
+                            for name in names {
+                                let rom = RomChoice {
+                                    path: path.clone(),
+                                    entry: Some(name),
+                                };
+
+                                entries.push(BrowserEntry {
+                                    title: title_for(&rom),
+                                    rom,
+                                });
+                            }
+
+                            continue;
+                        },
+                        Ok(_) => {},
+                        Err(e) => {
+                            println!("Unable to read {}: {}", path, e);
+                            continue;
+                        },
+                    }
+                }
+
+                let rom = RomChoice::file(&path);
+
+                entries.push(BrowserEntry {
+                    title: title_for(&rom),
+                    rom,
+                });
+            }
+        }
+
+        RomBrowser {
+            heading: "Select a ROM, or drop one onto the window".to_string(),
+            entries,
+            selected: 0,
+            scroll: 0,
+        }
+    }
+
+    // Lists the ROMs inside a single archive
+    pub fn for_archive(path: &str, names: Vec<String>) -> Self {
+        let entries = names.into_iter().map(|name| BrowserEntry {
+            title: name.clone(),
+            rom: RomChoice {
+                path: path.to_string(),
+                entry: Some(name),
+            },
+        }).collect();
+
+        RomBrowser {
+            heading: format!("Select a ROM from {}", file_name(path)),
+            entries,
+            selected: 0,
+            scroll: 0,
+        }
+    }
+
+    pub fn handle_event(&mut self, event: &Event) -> BrowserAction {
+        match event {
+            Event::Quit {..} => return BrowserAction::Quit,
+            Event::DropFile {filename, ..} => return BrowserAction::Load(RomChoice::file(filename)),
+            Event::KeyDown {keycode: Some(key), ..} => {
+                let last = self.entries.len().saturating_sub(1);
+
+                match *key {
+                    Keycode::Escape => return BrowserAction::Cancel,
+                    Keycode::Return | Keycode::KpEnter => {
+                        if let Some(entry) = self.entries.get(self.selected) {
+                            return BrowserAction::Load(entry.rom.clone());
+                        }
+                    },
+                    Keycode::Up => self.selected = self.selected.saturating_sub(1),
+                    Keycode::Down => self.selected = (self.selected + 1).min(last),
+                    Keycode::PageUp => self.selected = self.selected.saturating_sub(10),
+                    Keycode::PageDown => self.selected = (self.selected + 10).min(last),
+                    Keycode::Home => self.selected = 0,
+                    Keycode::End => self.selected = last,
+                    _ => {},
+                }
+            },
+            Event::MouseWheel {y, ..} => {
+                let last = self.entries.len().saturating_sub(1);
+
+                if *y > 0 {
+                    self.selected = self.selected.saturating_sub(1);
+                }
+                else if *y < 0 {
+                    self.selected = (self.selected + 1).min(last);
+                }
+            },
+            _ => {},
+        }
+
+        BrowserAction::None
+    }
+
+    pub fn draw(&mut self, canvas: &mut Canvas<Window>) {
+        let (width, height) = canvas.output_size().unwrap_or((0, 0));
+
+        canvas.set_draw_color(BACKGROUND);
+        canvas.clear();
+
+        text::draw_text(canvas, &self.heading, MARGIN, MARGIN, TEXT_SCALE, TEXT);
+
+        let footer_y = height as i32 - MARGIN - ROW_HEIGHT;
+        let visible_rows = ((footer_y - LIST_TOP) / ROW_HEIGHT).max(1) as usize;
+
+        // Keeps the selected entry on screen
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        }
+        else if self.selected >= self.scroll + visible_rows {
+            self.scroll = self.selected + 1 - visible_rows;
+        }
+
+        if self.entries.is_empty() {
+            text::draw_text(canvas, "No ROMs found", MARGIN, LIST_TOP, TEXT_SCALE, DIM_TEXT);
+        }
+
+        for (row, (i, entry)) in self.entries.iter().enumerate().skip(self.scroll).take(visible_rows).enumerate() {
+            let y = LIST_TOP + row as i32 * ROW_HEIGHT;
+
+            if i == self.selected {
+                canvas.set_draw_color(HIGHLIGHT);
+                let _ = canvas.fill_rect(Rect::new(0, y - TEXT_SCALE as i32, width, ROW_HEIGHT as u32));
+            }
+
+            text::draw_text(canvas, &entry.title, MARGIN, y, TEXT_SCALE, TEXT);
+        }
+
+        text::draw_text(canvas, "Up/Down: choose   Enter: load   Esc: back", MARGIN, footer_y, TEXT_SCALE, DIM_TEXT);
+
+        canvas.present();
+    }
+}
+
+fn file_name(path: &str) -> String {
+    Path::new(path).file_name().map_or(path.to_string(), |name| name.to_string_lossy().to_string())
+}
+
+// Looks the ROM up in its folder's titles file, falling back to its file name
+fn title_for(rom: &RomChoice) -> String {
+    let path = Path::new(&rom.path);
+    let name = file_name(&rom.path);
+    let titles_path = path.parent().unwrap_or(Path::new("")).join(TITLES_FILE);
+
+    let title = if titles_path.exists() {
+        Config::load(&titles_path.to_string_lossy()).ok().and_then(|titles| {
+            match &rom.entry {
+                Some(entry) => titles.get_or_warn::<String>(&format!("{}/{}", name, entry)),
+                None => titles.get_or_warn::<String>(&name),
+            }
+        })
+    }
+    else {
+        None
+    };
+
+    match (title, &rom.entry) {
+        (Some(title), _) => title,
+        (None, Some(entry)) => format!("{} / {}", name, entry),
+        (None, None) => name,
+    }
+}
+
+fn find_roms(folder: &Path, files: &mut Vec<PathBuf>) {
+    let dir = match fs::read_dir(folder) {
+        Ok(dir) => dir,
+        Err(_) => return,
+    };
+
+    for entry in dir.flatten() {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        if name.starts_with('.') || name == TITLES_FILE {
+            continue;
+        }
+
+        if path.is_dir() {
+            find_roms(&path, files);
+        }
+        else if loader::is_loadable(&path.to_string_lossy()) {
+            files.push(path);
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::beeper::{self, Beeper, BeeperSettings};
+use crate::palette::Palette;
+use crate::wav::WavWriter;
+
+pub const CAPTURE_FOLDER: &str = "captures";
+
+const FRAME_RATE: u64 = 60;
+const SAMPLES_PER_FRAME: usize = beeper::SAMPLE_RATE as usize / FRAME_RATE as usize;
+
+// Most GIF viewers treat frames shorter than this (in hundredths of a second) as far longer, so none are written shorter
+const MIN_GIF_DELAY: u64 = 2;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum CaptureKind {
+    // An animated GIF, with unchanged frames merged into one
+    Gif,
+    // A folder holding every frame as a PPM image and the sound as a WAV, ready to be encoded into a video
+    Frames,
+}
+
+enum Output {
+    Gif {
+        encoder: gif::Encoder<BufWriter<File>>,
+        // The frame being shown and the frame number it started on. It is only written once it changes,
+        // as that is when it is known how long it lasts
+        pending: Option<(Vec<u8>, u64)>,
+    },
+    Frames {
+        folder: PathBuf,
+        audio: WavWriter,
+        beeper: Beeper,
+    },
+}
+
+// A recording of the screen (and for frames, the sound) at 60 frames per second
+pub struct Capture {
+    output: Output,
+    path: String,
+    palette: Palette,
+    scale: usize,
+    width: usize,
+    height: usize,
+    frame: u64,
+}
+
+impl Capture {
+    pub fn start<R: AsRef<[bool]>>(kind: CaptureKind, name: &str, video: &[R], palette: &Palette, scale: u32, sound: BeeperSettings) -> Result<Self, String> {
+        let scale = scale.max(1) as usize;
+        let height = video.len();
+        let width = video.first().map_or(0, |row| row.as_ref().len());
+
+        fs::create_dir_all(CAPTURE_FOLDER).map_err(|e| format!("Unable to create {}: {}", CAPTURE_FOLDER, e))?;
+
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |t| t.as_secs());
+        let stem = PathBuf::from(CAPTURE_FOLDER).join(format!("{}-{}", name, time));
+
+        let (output, path) = match kind {
+            CaptureKind::Gif => {
+                let path = stem.with_extension("gif");
+                let file = File::create(&path).map_err(|e| format!("Unable to create {}: {}", path.display(), e))?;
+
+                let colours = [palette.background(), palette.foreground()];
+                let global_palette: Vec<u8> = colours.iter().flat_map(|c| [c.r, c.g, c.b]).collect();
+
+                let mut encoder = gif::Encoder::new(BufWriter::new(file), (width * scale) as u16, (height * scale) as u16, &global_palette)
+                    .map_err(|e| format!("Unable to write {}: {}", path.display(), e))?;
+
+                encoder.set_repeat(gif::Repeat::Infinite).map_err(|e| format!("Unable to write {}: {}", path.display(), e))?;
+
+                (Output::Gif {encoder, pending: None}, path)
+            },
+            CaptureKind::Frames => {
+                fs::create_dir_all(&stem).map_err(|e| format!("Unable to create {}: {}", stem.display(), e))?;
+
+                let audio = WavWriter::create(&stem.join("audio.wav").to_string_lossy(), beeper::SAMPLE_RATE as u32)?;
+
+                (Output::Frames {folder: stem.clone(), audio, beeper: Beeper::new(sound, beeper::SAMPLE_RATE)}, stem)
+            },
+        };
+
+        Ok(Capture {
+            output,
+            path: path.to_string_lossy().to_string(),
+            palette: *palette,
+            scale,
+            width,
+            height,
+            frame: 0,
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    // Called once per frame with the video memory and whether the beeper is sounding
+    pub fn record_frame<R: AsRef<[bool]>>(&mut self, video: &[R], beeping: bool) -> Result<(), String> {
+        if video.len() != self.height || video.first().map_or(0, |row| row.as_ref().len()) != self.width {
+            return Err("The screen resolution changed, so the recording was stopped".to_string());
+        }
+
+        let pixels = self.scaled(video);
+        let frame = self.frame;
+
+        match &mut self.output {
+            Output::Gif {encoder, pending} => {
+                match pending {
+                    Some((shown, _)) if *shown == pixels => {},
+                    // Too short to show on its own, so it is replaced by the new frame
+                    Some((shown, start)) if centiseconds(frame) - centiseconds(*start) < MIN_GIF_DELAY => *shown = pixels,
+                    _ => {
+                        if let Some((shown, start)) = pending.take() {
+                            write_gif_frame(encoder, &self.path, shown, self.width * self.scale, self.height * self.scale, start, frame)?;
+                        }
+
+                        *pending = Some((pixels, frame));
+                    },
+                }
+            },
+            Output::Frames {folder, audio, beeper} => {
+                let path = folder.join(format!("frame_{:06}.ppm", frame));
+                let colours = [self.palette.background(), self.palette.foreground()];
+
+                let mut image = format!("P6\n{} {}\n255\n", self.width * self.scale, self.height * self.scale).into_bytes();
+                image.extend(pixels.iter().flat_map(|&i| [colours[i as usize].r, colours[i as usize].g, colours[i as usize].b]));
+
+                File::create(&path)
+                    .and_then(|mut file| file.write_all(&image))
+                    .map_err(|e| format!("Unable to write {}: {}", path.display(), e))?;
+
+                beeper.set_gate(beeping);
+
+                let samples: Vec<f32> = (0..SAMPLES_PER_FRAME).map(|_| beeper.next_sample()).collect();
+                audio.write(&samples)?;
+            },
+        }
+
+        self.frame += 1;
+
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<(), String> {
+        match self.output {
+            Output::Gif {mut encoder, pending} => {
+                if let Some((shown, start)) = pending {
+                    write_gif_frame(&mut encoder, &self.path, shown, self.width * self.scale, self.height * self.scale, start, self.frame.max(start + 1))?;
+                }
+
+                encoder.into_inner()
+                    .and_then(|mut file| file.flush().map_err(gif::EncodingError::from))
+                    .map_err(|e| format!("Unable to write {}: {}", self.path, e))
+            },
+            Output::Frames {audio, ..} => audio.finish(),
+        }
+    }
+
+    // Palette indices for every pixel of the screen once it is scaled up
+    fn scaled<R: AsRef<[bool]>>(&self, video: &[R]) -> Vec<u8> {
+        let mut pixels = Vec::with_capacity(self.width * self.scale * self.height * self.scale);
+
+        for row in video {
+            let line: Vec<u8> = row.as_ref().iter().flat_map(|&lit| std::iter::repeat_n(lit as u8, self.scale)).collect();
+
+            for _ in 0..self.scale {
+                pixels.extend_from_slice(&line);
+            }
+        }
+
+        pixels
+    }
+}
+
+// When a frame starts, in the hundredths of a second GIF delays are measured in. Working from the frame number
+// rather than adding up rounded delays keeps the GIF from drifting out of time
+fn centiseconds(frame: u64) -> u64 {
+    (frame * 100 + FRAME_RATE / 2) / FRAME_RATE
+}
+
+fn write_gif_frame(encoder: &mut gif::Encoder<BufWriter<File>>, path: &str, pixels: Vec<u8>, width: usize, height: usize, start: u64, end: u64) -> Result<(), String> {
+    let mut frame = gif::Frame::from_indexed_pixels(width as u16, height as u16, pixels, None);
+    frame.delay = (centiseconds(end) - centiseconds(start)) as u16;
+
+    encoder.write_frame(&frame).map_err(|e| format!("Unable to write {}: {}", path, e))
+}
@@ -1,12 +1,18 @@
-extern crate rand;
+use std::fmt;
+use std::str::FromStr;
 
-use rand::Rng;
+use crate::movie;
+use crate::rng::RandomSource;
+use crate::timing::{self, TimingMode};
 
 use crate::SCREEN_WIDTH;
 use crate::SCREEN_HEIGHT;
 
 const USE_DOUBLE_DABBLE: bool = true;
 
+// Where programs are loaded unless told otherwise. Everything below it belonged to the original interpreter
+pub const DEFAULT_LOAD_ADDRESS: u16 = 0x200;
+
 const FONT_SIZE: usize = 80;
 const FONT_SET: [u8; FONT_SIZE] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -27,6 +33,58 @@ const FONT_SET: [u8; FONT_SIZE] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ResetKind {
+    // Restarts the CPU and restores the ROM, leaving the rest of RAM as the program left it
+    Soft,
+    // Clears all of RAM as if the machine had been switched off and on again
+    Hard,
+}
+
+// What finishes FX0A's wait for a key
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum KeyWait {
+    // The key going down, as most later interpreters do
+    Press,
+    // The key being let go after it went down, as the COSMAC VIP did
+    Release,
+}
+
+impl fmt::Display for KeyWait {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeyWait::Press => write!(f, "press"),
+            KeyWait::Release => write!(f, "release"),
+        }
+    }
+}
+
+impl FromStr for KeyWait {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "press" => Ok(KeyWait::Press),
+            "release" => Ok(KeyWait::Release),
+            _ => Err(format!("\"{}\" is not a key wait, expected press or release", s)),
+        }
+    }
+}
+
+// The parts of the CPU state that can carry over when a ROM is swapped for a new build of itself.
+// The program counter and stack aren't included as the code they point into will likely have moved
+#[derive(Clone)]
+pub struct RegisterSnapshot {
+    registers: [u8; 16],
+    i_reg: u16,
+    delay_timer: u8,
+    sound_timer: u8,
+}
+
+const STATE_MAGIC: &[u8; 4] = b"C8ST";
+const STATE_VERSION: u8 = 3;
+
 pub struct Chip8 {
     memory: [u8; 4096],
     video: [[bool; SCREEN_WIDTH]; SCREEN_HEIGHT],
@@ -39,13 +97,23 @@ pub struct Chip8 {
     stack: [u16; 16],
     keyboard: [bool; 16],
     keyboard_prev: [bool; 16],
-    keypress_wait: bool,
+    // Set while FX0A is waiting for a key, to the register it will store the key in
+    waiting_register: Option<u8>,
+    // The key that went down during the wait, when waiting for it to be released
+    waiting_key: Option<u8>,
+    key_wait: KeyWait,
     update_screen: bool,
-    done_reset: bool,
+    rom: Vec<u8>,
+    rom_start: u16,
+    rng: Box<dyn RandomSource>,
+    timing: TimingMode,
+    frame_cycles: u32,
+    display_wait: bool,
+    vblank: bool,
 }
 
 impl Chip8 {
-    pub fn init() -> Self {
+    pub fn init(rng: Box<dyn RandomSource>, timing: TimingMode) -> Self {
         let mut chip8 = Chip8 {
             memory: [0; 4096],
             video: [[false; SCREEN_WIDTH]; SCREEN_HEIGHT],
@@ -53,14 +121,22 @@ impl Chip8 {
             i_reg: 0,
             delay_timer: 0,
             sound_timer: 0,
-            pc: 0x200,
+            pc: DEFAULT_LOAD_ADDRESS,
             sp: 0,
             stack: [0; 16],
             keyboard: [false; 16],
             keyboard_prev: [false; 16],
-            keypress_wait: false,
+            waiting_register: None,
+            waiting_key: None,
+            key_wait: KeyWait::Release,
             update_screen: false,
-            done_reset: false,
+            rom: Vec::new(),
+            rom_start: DEFAULT_LOAD_ADDRESS,
+            rng,
+            timing,
+            frame_cycles: 0,
+            display_wait: false,
+            vblank: false,
         };
 
         for i in 0..FONT_SIZE {
@@ -70,30 +146,36 @@ impl Chip8 {
         chip8
     }
 
-    pub fn reset(&mut self) {
-        self.memory = [0; 4096];
+    // Restarts the loaded ROM from the beginning
+    pub fn reset(&mut self, kind: ResetKind) {
+        if kind == ResetKind::Hard {
+            self.memory = [0; 4096];
+
+            for i in 0..FONT_SIZE {
+                self.memory[i] = FONT_SET[i];
+            }
+        }
+
+        // The ROM may have modified itself while running, so it is always restored from the original image
+        let start: usize = self.rom_start as usize;
+        let end: usize = start + self.rom.len();
+        self.memory[start..end].copy_from_slice(&self.rom);
+
         self.video = [[false; SCREEN_WIDTH]; SCREEN_HEIGHT];
         self.registers = [0; 16];
         self.i_reg = 0;
         self.delay_timer = 0;
         self.sound_timer = 0;
-        self.pc = 0x200;
+        self.pc = self.rom_start;
         self.sp = 0;
         self.stack = [0; 16];
         self.keyboard = [false; 16];
         self.keyboard_prev = [false; 16];
-        self.keypress_wait = false;
-        self.update_screen = false;
-
-        for i in 0..FONT_SIZE {
-            self.memory[i] = FONT_SET[i];
-        }
-
-        self.done_reset = true;
-    }
-
-    pub fn has_done_reset(&mut self) -> bool {
-        self.done_reset
+        self.waiting_register = None;
+        self.waiting_key = None;
+        self.update_screen = true;
+        self.frame_cycles = 0;
+        self.vblank = false;
     }
 
     pub fn key_down(&mut self, key: usize) {
@@ -108,7 +190,16 @@ impl Chip8 {
         }
     }
 
+    pub fn is_key_down(&self, key: usize) -> bool {
+        key < 16 && self.keyboard[key]
+    }
+
+    // Called once per frame, which is also when a new frame's worth of cycles becomes available.
+    // Any cycles an instruction ran over by are carried into the next frame
     pub fn update_timers(&mut self) {
+        self.frame_cycles = self.frame_cycles.saturating_sub(self.timing.frame_budget());
+        self.vblank = true;
+
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
         }
@@ -138,19 +229,301 @@ impl Chip8 {
         &self.video
     }
 
-    pub fn load_rom(&mut self, buf: &Vec<u8>) {
-        let start: usize = 0x200; // May be better as a const value for starting address
+    pub fn snapshot_registers(&self) -> RegisterSnapshot {
+        RegisterSnapshot {
+            registers: self.registers,
+            i_reg: self.i_reg,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+        }
+    }
+
+    pub fn restore_registers(&mut self, snapshot: &RegisterSnapshot) {
+        self.registers = snapshot.registers;
+        self.i_reg = snapshot.i_reg;
+        self.delay_timer = snapshot.delay_timer;
+        self.sound_timer = snapshot.sound_timer;
+    }
+
+    // Serialises everything needed to carry on from this exact point. The keyboard isn't included as it reflects
+    // what the player is holding now, not what they held when the state was saved
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = Vec::new();
+
+        state.extend_from_slice(STATE_MAGIC);
+        state.push(STATE_VERSION);
+
+        // Which program the state belongs to, so it can't be loaded over a different one (or the same one at another address)
+        state.extend_from_slice(&movie::rom_hash(&self.rom).to_be_bytes());
+        state.extend_from_slice(&self.rom_start.to_be_bytes());
+
+        // The settings the program was running under, which are restored along with it
+        match self.timing {
+            TimingMode::Fixed(instructions_per_frame) => {
+                state.push(0);
+                state.extend_from_slice(&instructions_per_frame.to_be_bytes());
+            },
+            TimingMode::CosmacVip => {
+                state.push(1);
+                state.extend_from_slice(&0u32.to_be_bytes());
+            },
+        }
+
+        state.push(self.display_wait as u8);
+        state.push(self.key_wait as u8);
+
+        state.extend_from_slice(&self.memory);
+
+        for row in self.video.iter() {
+            state.extend(row.iter().map(|&pixel| pixel as u8));
+        }
+
+        state.extend_from_slice(&self.registers);
+        state.extend_from_slice(&self.i_reg.to_be_bytes());
+        state.push(self.delay_timer);
+        state.push(self.sound_timer);
+        state.extend_from_slice(&self.pc.to_be_bytes());
+        state.push(self.sp);
+
+        for address in self.stack.iter() {
+            state.extend_from_slice(&address.to_be_bytes());
+        }
+
+        // 0xFF stands for none, as neither can be over 0xF
+        state.push(self.waiting_register.unwrap_or(0xFF));
+        state.push(self.waiting_key.unwrap_or(0xFF));
+        state.extend_from_slice(&self.frame_cycles.to_be_bytes());
+        state.push(self.vblank as u8);
+        state.extend_from_slice(&self.rng.state().to_be_bytes());
+
+        state
+    }
+
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        let memory_size = self.memory.len();
+        let video_size = SCREEN_WIDTH * SCREEN_HEIGHT;
+
+        if state.len() != self.save_state().len() || &state[..4] != STATE_MAGIC || state[4] != STATE_VERSION {
+            return Err("not a save state from this version of the emulator".to_string());
+        }
+
+        let mut pos = 5;
+        let mut take = |len: usize| {
+            let bytes = &state[pos..pos + len];
+            pos += len;
+            bytes
+        };
+
+        // Everything is checked before anything is changed, so a state that can't be loaded leaves the machine as it was
+        let rom_hash = u64::from_be_bytes(take(8).try_into().unwrap());
+        let rom_start = u16::from_be_bytes([take(1)[0], take(1)[0]]);
+
+        if rom_hash != movie::rom_hash(&self.rom) || rom_start != self.rom_start {
+            return Err("the state was saved from a different build of the ROM, or at a different load address".to_string());
+        }
+
+        let timing_kind = take(1)[0];
+        let instructions_per_frame = u32::from_be_bytes(take(4).try_into().unwrap());
+
+        let timing = match timing_kind {
+            0 if instructions_per_frame > 0 => TimingMode::Fixed(instructions_per_frame),
+            1 => TimingMode::CosmacVip,
+            _ => return Err("the state has an invalid timing mode".to_string()),
+        };
+
+        let display_wait = take(1)[0] != 0;
+        let key_wait = if take(1)[0] == KeyWait::Press as u8 { KeyWait::Press } else { KeyWait::Release };
+
+        self.timing = timing;
+        self.display_wait = display_wait;
+        self.key_wait = key_wait;
+
+        self.memory.copy_from_slice(take(memory_size));
+
+        let video = take(video_size);
+
+        for (y, row) in self.video.iter_mut().enumerate() {
+            for (x, pixel) in row.iter_mut().enumerate() {
+                *pixel = video[y * SCREEN_WIDTH + x] != 0;
+            }
+        }
+
+        self.registers.copy_from_slice(take(16));
+        self.i_reg = u16::from_be_bytes([take(1)[0], take(1)[0]]);
+        self.delay_timer = take(1)[0];
+        self.sound_timer = take(1)[0];
+        self.pc = u16::from_be_bytes([take(1)[0], take(1)[0]]);
+        self.sp = take(1)[0];
+
+        for address in self.stack.iter_mut() {
+            *address = u16::from_be_bytes([take(1)[0], take(1)[0]]);
+        }
+
+        self.waiting_register = Some(take(1)[0]).filter(|&register| register < 16);
+        self.waiting_key = Some(take(1)[0]).filter(|&key| key < 16);
+        self.frame_cycles = u32::from_be_bytes(take(4).try_into().unwrap());
+        self.vblank = take(1)[0] != 0;
+        self.rng.set_state(u64::from_be_bytes(take(8).try_into().unwrap()));
+
+        self.keyboard_prev = self.keyboard;
+        self.update_screen = true;
+
+        Ok(())
+    }
+
+    // Loads a program at the given address (0x200 for most programs, 0x600 for ETI-660 ones) and starts running it from there
+    pub fn load_rom(&mut self, buf: &[u8], address: u16) -> Result<(), String> {
+        let start: usize = address as usize;
         let end: usize = start + buf.len();
+
+        if buf.is_empty() {
+            return Err("ROM is empty".to_string());
+        }
+
+        if address < DEFAULT_LOAD_ADDRESS {
+            return Err(format!("Load address {:#05X} is inside the interpreter's memory, it must be at least {:#05X}", address, DEFAULT_LOAD_ADDRESS));
+        }
+
+        if end > self.memory.len() {
+            return Err(format!("ROM is {} bytes, but only {} bytes fit between {:#05X} and the end of the {} bytes of memory",
+                buf.len(), self.memory.len().saturating_sub(start), address, self.memory.len()));
+        }
+
+        // Clears any previously loaded ROM so none of it is left behind when a shorter one replaces it
+        let old_start: usize = self.rom_start as usize;
+
+        for byte in self.memory[old_start..old_start + self.rom.len()].iter_mut() {
+            *byte = 0;
+        }
+
         self.memory[start..end].copy_from_slice(buf);
 
-        self.done_reset = false;
+        self.rom = buf.to_vec();
+        self.rom_start = address;
+        self.pc = address;
+
+        Ok(())
+    }
+
+    // When enabled, DXYN waits for the start of the next frame before drawing, as it did on the COSMAC VIP.
+    // The VIP timing mode always waits, as the wait is part of its timing
+    pub fn set_display_wait(&mut self, enabled: bool) {
+        self.display_wait = enabled;
+    }
+
+    pub fn display_wait(&self) -> bool {
+        self.display_wait
+    }
+
+    // Takes effect from the next instruction, so the speed can be changed while a ROM is running
+    pub fn set_timing(&mut self, timing: TimingMode) {
+        self.timing = timing;
+    }
+
+    pub fn timing(&self) -> TimingMode {
+        self.timing
+    }
+
+    pub fn set_key_wait(&mut self, key_wait: KeyWait) {
+        self.key_wait = key_wait;
+    }
+
+    pub fn key_wait(&self) -> KeyWait {
+        self.key_wait
+    }
+
+    // The register FX0A will store the key in, while it is waiting for one
+    pub fn waiting_for_key(&self) -> Option<usize> {
+        self.waiting_register.map(|register| register as usize)
+    }
+
+    fn waits_for_display(&self) -> bool {
+        self.display_wait || self.timing == TimingMode::CosmacVip
+    }
+
+    // The cycles used so far this frame, out of the timing mode's frame budget
+    pub fn frame_cycles(&self) -> u32 {
+        self.frame_cycles
+    }
+
+    // Whether the instructions run since the last call to update_timers have used up the frame
+    pub fn frame_complete(&self) -> bool {
+        self.frame_cycles >= self.timing.frame_budget()
     }
 
     pub fn tick(&mut self) {
+        // Nothing else runs while FX0A waits, and each check for the key takes the rest of the frame
+        if self.waiting_register.is_some() {
+            self.check_key_wait();
+
+            self.keyboard_prev = self.keyboard;
+            self.vblank = false;
+
+            if self.waiting_register.is_some() {
+                self.frame_cycles = self.frame_cycles.max(self.timing.frame_budget());
+            }
+
+            return;
+        }
+
+        let pc = self.pc;
         let opcode = self.fetch();
+        let vx = self.registers[((opcode & 0x0F00) >> 8) as usize];
+
+        // Unless this is the first instruction since the last frame ended, a sprite can't be drawn until the next vertical blank.
+        // DXYN is rewound so it runs again at the start of the next frame, and the rest of this frame is spent waiting
+        if opcode & 0xF000 == 0xD000 && self.waits_for_display() && !self.vblank {
+            self.pc = pc;
+            self.frame_cycles = self.frame_cycles.max(self.timing.frame_budget());
+
+            return;
+        }
+
         self.execute(opcode);
 
         self.keyboard_prev = self.keyboard;
+        self.vblank = false;
+
+        match self.timing {
+            TimingMode::Fixed(_) => {
+                self.frame_cycles += 1;
+            },
+            TimingMode::CosmacVip => {
+                let skipped = self.pc == pc + 4;
+
+                self.frame_cycles += timing::vip_cycles(opcode, vx, skipped);
+            },
+        }
+    }
+
+    // Ends FX0A's wait if the key it is waiting for has gone down, or been let go, since the last instruction
+    fn check_key_wait(&mut self) {
+        let register = match self.waiting_register {
+            Some(register) => register as usize,
+            None => return,
+        };
+
+        if let Some(key) = self.waiting_key {
+            if !self.keyboard[key as usize] {
+                self.registers[register] = key;
+                self.waiting_register = None;
+                self.waiting_key = None;
+            }
+
+            return;
+        }
+
+        let pressed = (0..self.keyboard.len()).find(|&i| self.keyboard[i] && !self.keyboard_prev[i]);
+
+        if let Some(key) = pressed {
+            match self.key_wait {
+                KeyWait::Press => {
+                    self.registers[register] = key as u8;
+                    self.waiting_register = None;
+                },
+                KeyWait::Release => self.waiting_key = Some(key as u8),
+            }
+        }
     }
 
     fn fetch(&mut self) -> u16 {
@@ -332,7 +705,7 @@ impl Chip8 {
 
             // RND Vx, nn
             (0xC, _, _, _) => {
-                let num = rand::thread_rng().gen_range(0..=255);
+                let num = self.rng.next_byte();
 
                 self.registers[x] = num & nn;
             },
@@ -393,32 +766,10 @@ impl Chip8 {
             },
 
             // LD Vx, K
+            // Only keys that go down from now on count, the CPU then stops until one does (see tick)
             (0xF, _, 0x0, 0xA) => {
-                let mut key_pressed = false;            
-                let mut key_released = false;
-
-                for i in 0..self.keyboard.len() {
-                    if self.keyboard_prev[i] && !self.keyboard[i] {
-                        self.keypress_wait = false;
-                        self.registers[x] = i as u8;
-                        key_released = true;
-
-                        break;
-                    }
-                    else if !self.keyboard_prev[i] && self.keyboard[i] {
-                        self.keypress_wait = true;
-                        key_pressed = true;
-
-                         break;
-                    }
-                }
-
-                if key_released {
-                    return;
-                }
-                else if !key_pressed || self.keypress_wait {
-                    self.pc -= 2;
-                }
+                self.waiting_register = Some(x as u8);
+                self.waiting_key = None;
             },
 
             // LD DT, Vx
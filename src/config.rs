@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+pub const CONFIG_PATH: &str = "chip8.cfg";
+
+// Emulator settings read from "key = value" lines. Lines starting with # are comments
+#[derive(Default)]
+pub struct Config {
+    path: String,
+    values: HashMap<String, String>,
+}
+
+impl Config {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Unable to read {}: {}", path, e))?;
+        let mut values = HashMap::new();
+
+        for (line_num, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            let (key, value) = line.split_once('=')
+                .ok_or_else(|| format!("{}:{}: expected \"key = value\"", path, line_num + 1))?;
+
+            values.insert(key.trim().to_string(), value.trim().to_string());
+        }
+
+        Ok(Config {
+            path: path.to_string(),
+            values,
+        })
+    }
+
+    // Returns Ok(None) if the key isn't set, and an error if it is set to something that can't be parsed
+    pub fn get<T: FromStr>(&self, key: &str) -> Result<Option<T>, String> {
+        match self.values.get(key) {
+            Some(value) => value.parse().map(Some).map_err(|_| format!("{}: invalid value \"{}\" for {}", self.path, value, key)),
+            None => Ok(None),
+        }
+    }
+
+    // Sets a key in a config file, replacing the line that sets it if there is one so the rest of the file, comments included,
+    // is left as it was. The file is created if it doesn't exist
+    pub fn store(path: &Path, key: &str, value: &str) -> Result<(), String> {
+        let text = if path.exists() {
+            fs::read_to_string(path).map_err(|e| format!("Unable to read {}: {}", path.display(), e))?
+        }
+        else {
+            String::new()
+        };
+
+        let setting = format!("{} = {}", key, value);
+        let mut found = false;
+
+        let mut lines: Vec<String> = text.lines().map(|line| {
+            let is_key = line.split('#').next().unwrap_or("").split_once('=').is_some_and(|(k, _)| k.trim() == key);
+
+            if is_key && !found {
+                found = true;
+                setting.clone()
+            }
+            else {
+                line.to_string()
+            }
+        }).collect();
+
+        if !found {
+            lines.push(setting);
+        }
+
+        if let Some(folder) = path.parent() {
+            fs::create_dir_all(folder).map_err(|e| format!("Unable to create {}: {}", folder.display(), e))?;
+        }
+
+        fs::write(path, lines.join("\n") + "\n").map_err(|e| format!("Unable to write {}: {}", path.display(), e))
+    }
+
+    // Like get, but reports an invalid value and carries on as if it wasn't set
+    pub fn get_or_warn<T: FromStr>(&self, key: &str) -> Option<T> {
+        self.get(key).unwrap_or_else(|e| {
+            println!("{}", e);
+            None
+        })
+    }
+}
@@ -0,0 +1,316 @@
+use sdl2::controller::{Button, GameController};
+use sdl2::event::Event;
+use sdl2::GameControllerSubsystem;
+
+use std::fs;
+
+use crate::input::{InputEvent, KeyHolds};
+
+pub const PROFILE_PATH: &str = "controller.cfg";
+
+// Every button a profile is allowed to map, in the order they are listed in the profile file
+const MAPPABLE_BUTTONS: [Button; 12] = [
+    Button::DPadUp,
+    Button::DPadDown,
+    Button::DPadLeft,
+    Button::DPadRight,
+    Button::A,
+    Button::B,
+    Button::X,
+    Button::Y,
+    Button::LeftShoulder,
+    Button::RightShoulder,
+    Button::Back,
+    Button::Start,
+];
+
+pub struct ControllerProfile {
+    keys: [Option<usize>; MAPPABLE_BUTTONS.len()],
+}
+
+impl Default for ControllerProfile {
+    // Directions map to the 2/4/6/8 layout most ROMs use for movement
+    fn default() -> Self {
+        ControllerProfile {
+            keys: [
+                Some(0x2),
+                Some(0x8),
+                Some(0x4),
+                Some(0x6),
+                Some(0x5),
+                Some(0x0),
+                Some(0x7),
+                Some(0x9),
+                Some(0x1),
+                Some(0x3),
+                Some(0xE),
+                Some(0xF),
+            ],
+        }
+    }
+}
+
+impl ControllerProfile {
+    // Loads a profile made of "button = key" lines, where button is an SDL button name (e.g. "dpup", "leftshoulder")
+    // and key is a hex CHIP-8 key, or "none" to leave the button unmapped. Buttons not listed keep their default key
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Unable to read {}: {}", path, e))?;
+        let mut profile = ControllerProfile::default();
+
+        for (line_num, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            let (name, value) = line.split_once('=')
+                .ok_or_else(|| format!("{}:{}: expected \"button = key\"", path, line_num + 1))?;
+
+            let button = Button::from_string(name.trim())
+                .ok_or_else(|| format!("{}:{}: unknown button \"{}\"", path, line_num + 1, name.trim()))?;
+
+            let index = MAPPABLE_BUTTONS.iter().position(|&b| b == button)
+                .ok_or_else(|| format!("{}:{}: button \"{}\" cannot be mapped", path, line_num + 1, name.trim()))?;
+
+            let value = value.trim();
+
+            profile.keys[index] = if value.eq_ignore_ascii_case("none") {
+                None
+            }
+            else {
+                match usize::from_str_radix(value.trim_start_matches("0x"), 16) {
+                    Ok(key) if key < 16 => Some(key),
+                    _ => return Err(format!("{}:{}: \"{}\" is not a key between 0 and F", path, line_num + 1, value)),
+                }
+            };
+        }
+
+        Ok(profile)
+    }
+
+    pub fn key_for(&self, button: Button) -> Option<usize> {
+        let index = MAPPABLE_BUTTONS.iter().position(|&b| b == button)?;
+
+        self.keys[index]
+    }
+}
+
+// An open controller and how many of its buttons are holding down each CHIP-8 key
+struct Connected {
+    controller: GameController,
+    held: [u8; 16],
+}
+
+pub struct Gamepads {
+    subsystem: GameControllerSubsystem,
+    controllers: Vec<Connected>,
+    profile: ControllerProfile,
+}
+
+impl Gamepads {
+    pub fn init(subsystem: GameControllerSubsystem, profile: ControllerProfile) -> Self {
+        let mut gamepads = Gamepads {
+            subsystem,
+            controllers: Vec::new(),
+            profile,
+        };
+
+        // Controllers plugged in before startup also generate a device added event,
+        // but opening them here means they work even if that event has already been drained
+        let count = gamepads.subsystem.num_joysticks().unwrap_or(0);
+
+        for index in 0..count {
+            gamepads.open(index);
+        }
+
+        gamepads
+    }
+
+    fn open(&mut self, joystick_index: u32) {
+        if !self.subsystem.is_game_controller(joystick_index) {
+            return;
+        }
+
+        match self.subsystem.open(joystick_index) {
+            Ok(controller) => {
+                if self.controllers.iter().all(|c| c.controller.instance_id() != controller.instance_id()) {
+                    println!("Controller connected: {}", controller.name());
+                    self.controllers.push(Connected {controller, held: [0; 16]});
+                }
+            },
+            Err(e) => println!("Unable to open controller {}: {}", joystick_index, e),
+        }
+    }
+
+    // How many of a controller's buttons are holding down a key
+    fn held(&mut self, which: u32, key: usize) -> Option<&mut u8> {
+        self.controllers.iter_mut()
+            .find(|c| c.controller.instance_id() == which)
+            .map(|c| &mut c.held[key])
+    }
+
+    // Handles hotplugging and button events, returning true if the event was consumed.
+    // Any resulting key changes are pushed onto `input` rather than applied directly so they can be recorded
+    pub fn handle_event(&mut self, event: &Event, holds: &mut KeyHolds, input: &mut Vec<InputEvent>) -> bool {
+        match *event {
+            Event::ControllerDeviceAdded {which, ..} => {
+                self.open(which);
+            },
+            Event::ControllerDeviceRemoved {which, ..} => {
+                if let Some(pos) = self.controllers.iter().position(|c| c.controller.instance_id() == which) {
+                    let connected = self.controllers.remove(pos);
+                    println!("Controller disconnected: {}", connected.controller.name());
+
+                    // Releases what this controller was holding so a key isn't stuck down when it is pulled mid-press.
+                    // Keys held on the keyboard or another controller are left alone
+                    for (k, &count) in connected.held.iter().enumerate() {
+                        for _ in 0..count {
+                            holds.release(k, input);
+                        }
+                    }
+                }
+            },
+            Event::ControllerButtonDown {which, button, ..} => {
+                if let Some(k) = self.profile.key_for(button) {
+                    if let Some(held) = self.held(which, k) {
+                        *held = held.saturating_add(1);
+                        holds.press(k, input);
+                    }
+                }
+            },
+            Event::ControllerButtonUp {which, button, ..} => {
+                if let Some(k) = self.profile.key_for(button) {
+                    if let Some(held) = self.held(which, k).filter(|held| **held > 0) {
+                        *held -= 1;
+                        holds.release(k, input);
+                    }
+                }
+            },
+            _ => return false,
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sdl2::{EventPump, JoystickSubsystem};
+
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    // SDL can only be initialised once at a time, so the tests take turns
+    static SDL_LOCK: Mutex<()> = Mutex::new(());
+
+    // Plugs in one of SDL's virtual joysticks, with its first two buttons mapped to A and B. Returns its device index.
+    // Needs no real hardware or display
+    fn attach_virtual(joysticks: &JoystickSubsystem, controllers: &GameControllerSubsystem) -> u32 {
+        let index = unsafe { sdl2::sys::SDL_JoystickAttachVirtual(sdl2::sys::SDL_JoystickType::SDL_JOYSTICK_TYPE_GAMECONTROLLER, 0, 2, 0) };
+        assert!(index >= 0, "{}", sdl2::get_error());
+
+        let guid = joysticks.device_guid(index as u32).unwrap();
+        controllers.add_mapping(&format!("{},Virtual test controller,a:b0,b:b1,", guid.string())).unwrap();
+
+        index as u32
+    }
+
+    fn set_button(instance_id: u32, button: i32, down: bool) {
+        unsafe {
+            let joystick = sdl2::sys::SDL_JoystickFromInstanceID(instance_id as i32);
+            sdl2::sys::SDL_JoystickSetVirtualButton(joystick, button, down as u8);
+        }
+    }
+
+    fn detach(instance_id: u32) {
+        let count = unsafe { sdl2::sys::SDL_NumJoysticks() };
+        let index = (0..count).find(|&i| unsafe { sdl2::sys::SDL_JoystickGetDeviceInstanceID(i) } == instance_id as i32).unwrap();
+
+        unsafe {
+            sdl2::sys::SDL_JoystickDetachVirtual(index);
+        }
+    }
+
+    // Changes to virtual joysticks only turn into events once SDL next updates its joysticks, so this keeps
+    // handling events for a little while and returns the input they produced
+    fn pump(event_pump: &mut EventPump, gamepads: &mut Gamepads, holds: &mut KeyHolds) -> Vec<InputEvent> {
+        let mut input = Vec::new();
+
+        for _ in 0..10 {
+            for event in event_pump.poll_iter() {
+                gamepads.handle_event(&event, holds, &mut input);
+            }
+
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        input
+    }
+
+    #[test]
+    fn virtual_button_press_maps_to_key() {
+        let _lock = SDL_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        sdl2::hint::set("SDL_JOYSTICK_ALLOW_BACKGROUND_EVENTS", "1");
+
+        let sdl_context = sdl2::init().unwrap();
+        let joysticks = sdl_context.joystick().unwrap();
+        let controllers = sdl_context.game_controller().unwrap();
+        let mut event_pump = sdl_context.event_pump().unwrap();
+
+        attach_virtual(&joysticks, &controllers);
+
+        let mut gamepads = Gamepads::init(controllers, ControllerProfile::default());
+        assert_eq!(gamepads.controllers.len(), 1);
+
+        let instance_id = gamepads.controllers[0].controller.instance_id();
+        let mut holds = KeyHolds::default();
+
+        pump(&mut event_pump, &mut gamepads, &mut holds);
+        set_button(instance_id, 0, true);
+
+        assert_eq!(pump(&mut event_pump, &mut gamepads, &mut holds), vec![InputEvent::KeyDown(0x5)]);
+        assert_eq!(gamepads.controllers[0].held[0x5], 1);
+    }
+
+    #[test]
+    fn disconnect_releases_only_that_controllers_keys() {
+        let _lock = SDL_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        sdl2::hint::set("SDL_JOYSTICK_ALLOW_BACKGROUND_EVENTS", "1");
+
+        let sdl_context = sdl2::init().unwrap();
+        let joysticks = sdl_context.joystick().unwrap();
+        let controllers = sdl_context.game_controller().unwrap();
+        let mut event_pump = sdl_context.event_pump().unwrap();
+
+        attach_virtual(&joysticks, &controllers);
+        attach_virtual(&joysticks, &controllers);
+
+        let mut gamepads = Gamepads::init(controllers, ControllerProfile::default());
+        assert_eq!(gamepads.controllers.len(), 2);
+
+        let first = gamepads.controllers[0].controller.instance_id();
+        let second = gamepads.controllers[1].controller.instance_id();
+        let mut holds = KeyHolds::default();
+
+        pump(&mut event_pump, &mut gamepads, &mut holds);
+
+        // Both hold A, which is key 5 in the default profile, and the second also holds B, which is key 0
+        set_button(first, 0, true);
+        set_button(second, 0, true);
+        set_button(second, 1, true);
+
+        let pressed = pump(&mut event_pump, &mut gamepads, &mut holds);
+        assert_eq!(pressed.iter().filter(|&&e| e == InputEvent::KeyDown(0x5)).count(), 1);
+        assert!(pressed.contains(&InputEvent::KeyDown(0x0)));
+
+        detach(second);
+
+        // Key 5 is still held by the first controller, so only key 0 is let go
+        assert_eq!(pump(&mut event_pump, &mut gamepads, &mut holds), vec![InputEvent::KeyUp(0x0)]);
+        assert_eq!(gamepads.controllers.len(), 1);
+        assert_eq!(gamepads.controllers[0].held[0x5], 1);
+    }
+}
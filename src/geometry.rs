@@ -0,0 +1,75 @@
+use sdl2::video::{FullscreenType, Window, WindowPos};
+
+use std::fs;
+
+use crate::config::Config;
+
+pub const GEOMETRY_PATH: &str = "window.cfg";
+
+// Where the window was and how big it was when the emulator was last closed, so it can open the same way next time
+pub struct WindowGeometry {
+    pub x: Option<i32>,
+    pub y: Option<i32>,
+    pub width: u32,
+    pub height: u32,
+    pub fullscreen: bool,
+}
+
+impl WindowGeometry {
+    // A missing or unreadable file gives a centred window of the default size
+    pub fn load(path: &str, default_width: u32, default_height: u32) -> Self {
+        let saved = Config::load(path).unwrap_or_default();
+
+        WindowGeometry {
+            x: saved.get_or_warn("x"),
+            y: saved.get_or_warn("y"),
+            width: saved.get_or_warn("width").filter(|&w| w > 0).unwrap_or(default_width),
+            height: saved.get_or_warn("height").filter(|&h| h > 0).unwrap_or(default_height),
+            fullscreen: saved.get_or_warn("fullscreen").unwrap_or(false),
+        }
+    }
+
+    pub fn position(&self) -> (WindowPos, WindowPos) {
+        match (self.x, self.y) {
+            (Some(x), Some(y)) => (WindowPos::Positioned(x), WindowPos::Positioned(y)),
+            _ => (WindowPos::Centered, WindowPos::Centered),
+        }
+    }
+
+    // Records where the window is now. Its size and position while fullscreen aren't kept, so that leaving
+    // fullscreen next time goes back to the window as it was before
+    pub fn update(&mut self, window: &Window) {
+        self.fullscreen = window.fullscreen_state() != FullscreenType::Off;
+
+        if !self.fullscreen {
+            let (x, y) = window.position();
+            let (width, height) = window.size();
+
+            self.x = Some(x);
+            self.y = Some(y);
+            self.width = width;
+            self.height = height;
+        }
+    }
+
+    pub fn save(&self, path: &str) {
+        let mut text = format!("width = {}\nheight = {}\nfullscreen = {}\n", self.width, self.height, self.fullscreen);
+
+        if let (Some(x), Some(y)) = (self.x, self.y) {
+            text.push_str(&format!("x = {}\ny = {}\n", x, y));
+        }
+
+        if let Err(e) = fs::write(path, text) {
+            println!("Unable to save the window position to {}: {}", path, e);
+        }
+    }
+}
+
+// Switches between a window and borderless fullscreen at the desktop's resolution
+pub fn toggle_fullscreen(window: &mut Window) {
+    let state = if window.fullscreen_state() == FullscreenType::Off { FullscreenType::Desktop } else { FullscreenType::Off };
+
+    if let Err(e) = window.set_fullscreen(state) {
+        println!("Unable to change fullscreen mode: {}", e);
+    }
+}
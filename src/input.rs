@@ -0,0 +1,97 @@
+use crate::chip8::{Chip8, ResetKind};
+
+// A change to the emulated machine's input, as produced by the keyboard, a controller or a movie file
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InputEvent {
+    KeyDown(usize),
+    KeyUp(usize),
+    Reset(ResetKind),
+}
+
+impl InputEvent {
+    pub fn apply(&self, chip8: &mut Chip8) {
+        match *self {
+            InputEvent::KeyDown(key) => chip8.key_down(key),
+            InputEvent::KeyUp(key) => chip8.key_up(key),
+            InputEvent::Reset(kind) => chip8.reset(kind),
+        }
+    }
+}
+
+// How many keyboard keys and controller buttons are holding each CHIP-8 key down, as several can be mapped to the
+// same one. Only the first press and the last release are passed on, so letting go of one doesn't release a key
+// that is still held by another
+#[derive(Default)]
+pub struct KeyHolds {
+    counts: [u8; 16],
+}
+
+impl KeyHolds {
+    pub fn press(&mut self, key: usize, input: &mut Vec<InputEvent>) {
+        if key < 16 {
+            self.counts[key] = self.counts[key].saturating_add(1);
+
+            if self.counts[key] == 1 {
+                input.push(InputEvent::KeyDown(key));
+            }
+        }
+    }
+
+    pub fn release(&mut self, key: usize, input: &mut Vec<InputEvent>) {
+        if key < 16 && self.counts[key] > 0 {
+            self.counts[key] -= 1;
+
+            if self.counts[key] == 0 {
+                input.push(InputEvent::KeyUp(key));
+            }
+        }
+    }
+
+    // Forgets every hold, for when the keys have all been let go regardless of what is still pressed
+    pub fn clear(&mut self) {
+        self.counts = [0; 16];
+    }
+}
+
+// Host input waiting to be given to the CHIP-8. Input is only read at a few points in each frame, so events are stamped
+// with the point they were read at and handed over from there. A key pressed and released between two points would
+// otherwise reach the CHIP-8 at the same instant and never be seen as held, so its release waits for the next point
+#[derive(Default)]
+pub struct InputQueue {
+    pending: Vec<(u64, InputEvent)>,
+    // The point each key last changed at
+    last_change: [Option<(u64, InputEvent)>; 16],
+}
+
+impl InputQueue {
+    pub fn schedule(&mut self, point: u64, events: impl IntoIterator<Item = InputEvent>) {
+        for event in events {
+            let key = match event {
+                InputEvent::KeyDown(key) | InputEvent::KeyUp(key) if key < 16 => key,
+                _ => {
+                    self.pending.push((point, event));
+                    continue;
+                },
+            };
+
+            let due = match self.last_change[key] {
+                Some((last, InputEvent::KeyDown(_))) if matches!(event, InputEvent::KeyUp(_)) && last >= point => last + 1,
+                Some((last, _)) => last.max(point),
+                None => point,
+            };
+
+            self.last_change[key] = Some((due, event));
+            self.pending.push((due, event));
+        }
+    }
+
+    // Moves everything due by the given point into the list, in the order it happened
+    pub fn take_due(&mut self, point: u64, out: &mut Vec<InputEvent>) {
+        out.extend(self.pending.iter().filter(|(due, _)| *due <= point).map(|&(_, event)| event));
+        self.pending.retain(|(due, _)| *due > point);
+    }
+
+    pub fn clear(&mut self) {
+        *self = InputQueue::default();
+    }
+}
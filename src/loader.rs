@@ -0,0 +1,114 @@
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+// Files inside an archive with one of these extensions are treated as ROMs
+const ROM_EXTENSIONS: [&str; 4] = ["ch8", "c8", "sc8", "xo8"];
+
+// A ROM file on disk, along with which file to use if it is an archive holding more than one ROM
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RomChoice {
+    pub path: String,
+    pub entry: Option<String>,
+}
+
+impl RomChoice {
+    pub fn file(path: &str) -> Self {
+        RomChoice {
+            path: path.to_string(),
+            entry: None,
+        }
+    }
+
+    // A name for files that belong to this ROM, such as its save states. It is made from the ROM's file name and,
+    // for archives, the entry within it
+    pub fn file_name_stem(&self) -> String {
+        let mut name = Path::new(&self.path).file_name().map_or("rom".to_string(), |name| name.to_string_lossy().to_string());
+
+        if let Some(entry) = &self.entry {
+            name.push('-');
+            name.extend(entry.chars().map(|c| if c.is_ascii_alphanumeric() || c == '.' { c } else { '_' }));
+        }
+
+        name
+    }
+}
+
+fn extension(path: &str) -> Option<String> {
+    Path::new(path).extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase())
+}
+
+// Whether a file is one the loader understands. Many ROMs have no extension at all, so those are accepted too
+pub fn is_loadable(path: &str) -> bool {
+    match extension(path) {
+        Some(e) => e == "zip" || ROM_EXTENSIONS.contains(&e.as_str()),
+        None => true,
+    }
+}
+
+pub fn is_archive(path: &str) -> bool {
+    extension(path).as_deref() == Some("zip")
+}
+
+// Loads a plain ROM or a ROM from a .zip archive
+pub fn load(choice: &RomChoice) -> Result<Vec<u8>, String> {
+    match extension(&choice.path).as_deref() {
+        Some("zip") => load_zip(choice),
+        _ => fs::read(&choice.path).map_err(|e| e.to_string()),
+    }
+}
+
+// Lists the files in an archive that could be ROMs
+pub fn archive_roms(path: &str) -> Result<Vec<String>, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let mut archive = zip::ZipArchive::new(BufReader::new(file)).map_err(|e| e.to_string())?;
+
+    let mut names = Vec::new();
+
+    for i in 0..archive.len() {
+        let entry = archive.by_index(i).map_err(|e| e.to_string())?;
+
+        if !entry.is_dir() {
+            names.push(entry.name().map_err(|e| e.to_string())?.to_string());
+        }
+    }
+
+    let is_rom = |name: &String| extension(name).is_some_and(|e| ROM_EXTENSIONS.contains(&e.as_str()));
+
+    // ROMs are often distributed with a bare name or an unusual extension, so if nothing looks like a ROM consider every file
+    let candidates: Vec<String> = names.iter().filter(|name| is_rom(name)).cloned().collect();
+
+    if candidates.is_empty() {
+        Ok(names)
+    }
+    else {
+        Ok(candidates)
+    }
+}
+
+fn load_zip(choice: &RomChoice) -> Result<Vec<u8>, String> {
+    let name = match &choice.entry {
+        Some(entry) => entry.clone(),
+        None => {
+            let mut candidates = archive_roms(&choice.path)?;
+
+            match candidates.len() {
+                0 => return Err("archive is empty".to_string()),
+                1 => candidates.remove(0),
+                _ => return Err("archive contains more than one ROM, choose one from the ROM browser".to_string()),
+            }
+        },
+    };
+
+    let file = File::open(&choice.path).map_err(|e| e.to_string())?;
+    let mut archive = zip::ZipArchive::new(BufReader::new(file)).map_err(|e| e.to_string())?;
+
+    let mut entry = archive.by_name(&name).map_err(|e| format!("{}: {}", name, e))?;
+    let mut bytes = Vec::new();
+
+    entry.read_to_end(&mut bytes).map_err(|e| format!("{}: {}", name, e))?;
+
+    Ok(bytes)
+}
@@ -1,21 +1,80 @@
 extern crate sdl2;
 
+mod beeper;
+use beeper::{Beeper, BeeperSettings};
+
+mod browser;
+use browser::{BrowserAction, RomBrowser};
+
+mod capture;
+use capture::{Capture, CaptureKind};
+
 mod chip8;
-use chip8::Chip8;
+use chip8::{Chip8, ResetKind};
+
+mod config;
+use config::Config;
+
+mod geometry;
+use geometry::WindowGeometry;
+
+mod gamepad;
+use gamepad::{ControllerProfile, Gamepads};
+
+mod input;
+use input::{InputEvent, InputQueue, KeyHolds};
+
+mod loader;
+use loader::RomChoice;
+
+mod movie;
+use movie::{MoviePlayer, MovieRecorder};
+
+mod recent;
+use recent::RecentRoms;
+
+mod offline;
+
+mod overlay;
+use overlay::{MenuAction, Overlay, Settings};
+
+mod palette;
+
+mod phosphor;
+use phosphor::{Persistence, Phosphor};
+
+mod renderer;
+use renderer::{Effects, Renderer, Scaling};
+
+mod rng;
+
+mod screenshot;
+
+mod scheduler;
+use scheduler::Scheduler;
+
+mod session;
+use session::Session;
+
+mod text;
+
+mod timing;
+use timing::TimingMode;
+
+mod watcher;
+
+mod wav;
 
-use sdl2::pixels::Color;
-use sdl2::rect::Rect;
 use sdl2::render::Canvas;
 use sdl2::video::Window;
-use sdl2::audio::{AudioCallback, AudioSpecDesired};
+use sdl2::audio::AudioSpecDesired;
 use sdl2::keyboard::Keycode;
 
-use std::fs::File;
-use std::io::Read;
 use std::env;
-use std::time::{Duration, Instant};
+use std::num::NonZeroU32;
 
-use sdl2::event::Event;
+use sdl2::event::{Event, WindowEvent};
+use sdl2::EventPump;
 
 const SCREEN_WIDTH: usize = 64;
 const SCREEN_HEIGHT: usize = 32;
@@ -24,153 +83,838 @@ const WINDOW_SCALE: u32 = 20;
 const WINDOW_WIDTH: u32 = (SCREEN_WIDTH as u32) * WINDOW_SCALE;
 const WINDOW_HEIGHT: u32 = (SCREEN_HEIGHT as u32) * WINDOW_SCALE;
 
+// The CHIP-8's timers count down at 60 Hz, and the CPU and everything shown are scheduled around them
+const TIMER_RATE: u32 = 60;
+
+// The CPU's speed is given as how many instructions it runs per timer tick
 const INSTRUCTIONS_PER_SECOND: u32 = 500;
-const WAIT_TIME: f64 = 1.0 / 60.0;
+const CYCLES_PER_TIMER_TICK: u32 = INSTRUCTIONS_PER_SECOND / TIMER_RATE;
 
-struct SquareWave {
-    phase_inc: f32,
-    phase: f32,
-    volume: f32
+// The speeds [ and ] step through, as multiples of normal speed
+const SPEEDS: [f32; 7] = [0.1, 0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+const NORMAL_SPEED: usize = 3;
+
+// How much the beeper's pitch and volume change with each press in the settings menu
+const PITCH_STEP: f32 = 20.0;
+const VOLUME_STEP: f32 = 0.05;
+
+enum Keys {
+    Ctrl,
+    Shift,
+    Alt,
+    R,
 }
 
-impl AudioCallback for SquareWave {
-    type Channel = f32;
+struct Options {
+    rom_path: Option<String>,
+    record_path: Option<String>,
+    play_path: Option<String>,
+    seed: Option<u64>,
+    timing: Option<TimingMode>,
+    display_wait: bool,
+    watch: bool,
+    keep_registers: bool,
+    load_address: Option<u16>,
+    render_audio: Option<String>,
+    frames: Option<u64>,
+}
 
-    // Generates the square wave
-    fn callback(&mut self, out: &mut [Self::Channel]) {
-        for x in out.iter_mut() {
-            *x = if self.phase <= 0.5 {
-                self.volume
-            }
-            else {
-                -self.volume
-            };
+// Addresses are given in hex, with or without a leading 0x
+fn parse_address(s: &str) -> Result<u16, String> {
+    let digits = s.trim_start_matches("0x").trim_start_matches("0X");
 
-            self.phase = (self.phase + self.phase_inc) % 1.0;
+    u16::from_str_radix(digits, 16).map_err(|_| format!("\"{}\" is not a hex address", s))
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut rom_path = None;
+    let mut record_path = None;
+    let mut play_path = None;
+    let mut seed = None;
+    let mut timing = None;
+    let mut display_wait = false;
+    let mut watch = false;
+    let mut keep_registers = false;
+    let mut load_address = None;
+    let mut render_audio = None;
+    let mut frames = None;
+
+    let mut iter = args.iter().skip(1);
+
+    while let Some(arg) = iter.next() {
+        let mut value = || iter.next().cloned().ok_or_else(|| format!("{} requires a value", arg));
+
+        match arg.as_str() {
+            "--record" => record_path = Some(value()?),
+            "--play" => play_path = Some(value()?),
+            "--timing" => timing = Some(value()?.replace(':', " ").parse::<TimingMode>()?),
+            "--cycles-per-tick" => timing = Some(TimingMode::Fixed(value()?.parse::<NonZeroU32>().map_err(|_| "--cycles-per-tick must be a whole number above 0".to_string())?.get())),
+            "--display-wait" => display_wait = true,
+            "--watch" => watch = true,
+            "--keep-registers" => keep_registers = true,
+            "--load-address" => load_address = Some(parse_address(&value()?)?),
+            "--render-audio" => render_audio = Some(value()?),
+            "--frames" => frames = Some(value()?.parse().map_err(|_| "--frames must be a whole number".to_string())?),
+            "--seed" => seed = Some(value()?.parse().map_err(|_| "--seed must be a whole number".to_string())?),
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+            _ => rom_path = Some(arg.clone()),
         }
     }
-}
 
-enum Keys {
-    CTRL,
-    R,
+    if record_path.is_some() && play_path.is_some() {
+        return Err("--record and --play cannot be used together".to_string());
+    }
+
+    if render_audio.is_some() && rom_path.is_none() {
+        return Err("--render-audio needs a ROM".to_string());
+    }
+
+    Ok(Options {
+        rom_path,
+        record_path,
+        play_path,
+        seed,
+        timing,
+        display_wait,
+        watch,
+        keep_registers,
+        load_address,
+        render_audio,
+        frames,
+    })
 }
 
 fn main() {
     let args: Vec<_> = env::args().collect();
 
-    if args.len() < 2 {
-        println!("No ROM Specified. Exiting...");
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            println!("{}. Exiting...", e);
+            println!("Usage: {} [rom] [--record <movie>] [--play <movie>] [--seed <n>] [--timing vip|fixed:<ipf>] [--cycles-per-tick <n>] [--display-wait] [--watch [--keep-registers]] [--load-address <hex>] [--render-audio <wav> [--frames <n>]]", args[0]);
+            return;
+        }
+    };
+
+    let config = if std::path::Path::new(config::CONFIG_PATH).exists() {
+        Config::load(config::CONFIG_PATH).unwrap_or_else(|e| {
+            println!("{}. Using the default settings", e);
+            Config::default()
+        })
+    }
+    else {
+        Config::default()
+    };
+
+    let mut player = match options.play_path.as_ref().map(|path| MoviePlayer::load(path)).transpose() {
+        Ok(player) => player,
+        Err(e) => {
+            println!("Unable to load movie: {}. Exiting...", e);
+            return;
+        }
+    };
+
+    // A movie is played back with the seed and timing it was recorded with, otherwise the command line takes priority over the config.
+    // The seed is always printed so a run that used a random one can still be reproduced
+    let seed = player.as_ref().and_then(|movie| movie.header().seed)
+        .or(options.seed)
+        .or_else(|| config.get_or_warn("seed"))
+        .unwrap_or_else(rand::random);
+
+    println!("Random seed: {}", seed);
+
+    // Rendering audio needs neither a window nor a sound device
+    if let Some(path) = &options.render_audio {
+        let rom = RomChoice::file(options.rom_path.as_deref().unwrap_or_default());
+
+        match offline::render_audio(path, &rom, &options, &config, player, seed, options.frames) {
+            Ok(frames) => println!("Rendered {} frames of audio to {}", frames, path),
+            Err(e) => {
+                // Scripts checking the output need to be able to tell that it failed
+                println!("Unable to render audio: {}", e);
+                std::process::exit(1);
+            },
+        }
+
         return;
     }
 
     // Set up SDL video and audio
     let sdl_context = sdl2::init().unwrap();
     let video_subsys = sdl_context.video().unwrap();
-    let window = video_subsys.window("CHIP-8 Emulator", WINDOW_WIDTH, WINDOW_HEIGHT).position_centered().opengl().build().expect("Unable to initialise window!");
+    // The window opens where it was last closed
+    let mut geometry = WindowGeometry::load(geometry::GEOMETRY_PATH, WINDOW_WIDTH, WINDOW_HEIGHT);
+    let mut window = video_subsys.window("CHIP-8 Emulator", geometry.width, geometry.height).resizable().opengl().build().expect("Unable to initialise window!");
+
+    let (x, y) = geometry.position();
+    window.set_position(x, y);
+    let _ = window.set_minimum_size(SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32);
+
+    if geometry.fullscreen {
+        geometry::toggle_fullscreen(&mut window);
+    }
 
     let mut canvas = window.into_canvas().present_vsync().build().unwrap();
+    let texture_creator = canvas.texture_creator();
     let mut event_pump = sdl_context.event_pump().unwrap();
 
+    let profile = match ControllerProfile::load(gamepad::PROFILE_PATH) {
+        Ok(profile) => profile,
+        Err(e) => {
+            if std::path::Path::new(gamepad::PROFILE_PATH).exists() {
+                println!("{}. Using the default controller profile", e);
+            }
+
+            ControllerProfile::default()
+        }
+    };
+
+    let mut gamepads = Gamepads::init(sdl_context.game_controller().unwrap(), profile);
+
     let audio_subsystem = sdl_context.audio().unwrap();
 
     let desired_spec = AudioSpecDesired {
-        freq: Some(44100),
+        freq: Some(beeper::SAMPLE_RATE),
         channels: Some(1),
         samples: None
     };
 
-    let device = audio_subsystem.open_playback(None, &desired_spec, |spec| {
-        SquareWave {
-            phase_inc: 440.0 / spec.freq as f32,
-            phase: 0.0,
-            volume: 0.25
-        }
+    let mut sound = BeeperSettings::from_config(&config);
+
+    // The beeper runs all the time and is switched on and off through its gate, so it can fade in and out without clicking
+    let mut device = audio_subsystem.open_playback(None, &desired_spec, |spec| {
+        Beeper::new(sound, spec.freq)
     }).unwrap();
 
-    // Initialises the CHIP-8 and loads the ROM
-    let mut chip8 = Chip8::init();
+    device.resume();
 
-    let mut rom = File::open(&args[1]).expect("Unable to open file!");
-    let mut buffer = Vec::new();
+    let mut recent = RecentRoms::load(recent::RECENT_PATH);
 
-    rom.read_to_end(&mut buffer).unwrap();
-    chip8.load_rom(&buffer);
+    let mut rom_folders = vec![browser::DEFAULT_ROM_FOLDER.to_string()];
 
-    let mut keys_down: [bool; 2] = Default::default();
+    if let Some(folders) = config.get_or_warn::<String>("rom_folders") {
+        rom_folders.extend(folders.split(';').map(|f| f.trim().to_string()).filter(|f| !f.is_empty()));
+    }
 
-    'running: loop {
-        let time = Instant::now();
+    let mut input: Vec<InputEvent> = Vec::new();
+    let mut holds = KeyHolds::default();
+
+    // Without a ROM on the command line, or with an archive that holds several, one is picked from the browser
+    let first_rom = match &options.rom_path {
+        Some(path) if loader::is_archive(path) => match loader::archive_roms(path) {
+            Ok(names) if names.len() > 1 => run_browser(&mut RomBrowser::for_archive(path, names), &mut event_pump, &mut canvas, &mut gamepads, &mut holds, &mut input),
+            _ => BrowserResult::Chosen(RomChoice::file(path)),
+        },
+        Some(path) => BrowserResult::Chosen(RomChoice::file(path)),
+        None => run_browser(&mut RomBrowser::for_folders(&rom_folders, &recent), &mut event_pump, &mut canvas, &mut gamepads, &mut holds, &mut input),
+    };
+
+    let first_rom = match first_rom {
+        BrowserResult::Chosen(rom) => rom,
+        _ => return,
+    };
+
+    let mut session = match Session::start(&first_rom, &options, &config, player.as_ref().map(|movie| movie.header()), seed) {
+        Ok(session) => session,
+        Err(e) => {
+            println!("Unable to load {}: {}. Exiting...", first_rom.path, e);
+            return;
+        }
+    };
+
+    recent.add(&session.rom);
+
+    let mut recorder = match options.record_path.as_ref().map(|path| MovieRecorder::create(path, &session.header).map_err(|e| format!("{}: {}", path, e))).transpose() {
+        Ok(recorder) => recorder,
+        Err(e) => {
+            println!("Unable to create movie file {}. Exiting...", e);
+            return;
+        }
+    };
+
+    if let Some(player) = &player {
+        if player.header().rom_hash != session.header.rom_hash {
+            println!("Warning: the movie was recorded with a different ROM and will likely desync");
+        }
+    }
+
+    let mut keys_down: [bool; 4] = Default::default();
+
+    // Read input is held here until the point in the frame it is due at
+    let mut queue = InputQueue::default();
+    let mut input_point: u64 = 0;
+    let polls_per_frame = config.get_or_warn("input_polls_per_frame").unwrap_or(1u32).max(1);
+
+    let mut frame: u64 = 0;
+
+    let screenshot_scale = config.get_or_warn("screenshot_scale").unwrap_or(WINDOW_SCALE);
+    let capture_scale = config.get_or_warn("capture_scale").unwrap_or(4);
+    let mut capture: Option<Capture> = None;
 
-        // Runs the desired amount of instructions per second that would happen in a frame
-        for _ in 0..INSTRUCTIONS_PER_SECOND / 60 {
+    let mut overlay = Overlay::new(config.get_or_warn("show_fps").unwrap_or(false));
+
+    let mut renderer = Renderer::new(&texture_creator, Effects::from_config(&config), config.get_or_warn("scaling").unwrap_or(Scaling::Aspect));
+
+    let mut phosphor = Phosphor::new(
+        config.get_or_warn("persistence").unwrap_or(Persistence::Off),
+        config.get_or_warn("phosphor_decay").unwrap_or(phosphor::DEFAULT_DECAY),
+    );
+
+    let mut scheduler = Scheduler::new(TIMER_RATE);
+
+    let mut speed = NORMAL_SPEED;
+    let mut fast_forward = false;
+
+    // While frame stepping, emulation only moves on a tick at a time when asked to
+    let mut stepping = false;
+    let mut advance = false;
+
+    'running: loop {
+        // Nothing runs while the pause menu is open, the menu just keeps being redrawn over the last frame
+        if overlay.is_paused() {
             for event in event_pump.poll_iter() {
-                match event {
-                    Event::Quit {..} => {
-                        break 'running;
+                // Controllers are still looked after, so ones plugged in now work and buttons let go of now don't stay held
+                if gamepads.handle_event(&event, &mut holds, &mut input) {
+                    continue;
+                }
+
+                // The menu only acts on keys going down, so releases are still seen to here
+                if let Event::KeyUp {keycode: Some(key), ..} = event {
+                    key_up(key, &mut keys_down, &mut fast_forward, &mut scheduler, &mut holds, &mut input);
+                    overlay.set_status(speed_status(stepping, fast_forward, speed));
+                }
+
+                match overlay.handle_event(&event) {
+                    MenuAction::None => {},
+                    MenuAction::Reset => input.push(InputEvent::Reset(ResetKind::Soft)),
+                    MenuAction::SaveState(slot) => match session.save_state(slot) {
+                        Ok(()) => overlay.show_message(&format!("State saved to slot {}", slot)),
+                        Err(e) => overlay.show_message(&e),
                     },
-                    Event::KeyDown {keycode: Some(key), ..} => {
-                        if let Some(k) = process_keycode(key) {
-                            chip8.key_down(k);
-                        }
+                    MenuAction::LoadState(slot) => match session.load_state(slot) {
+                        Ok(()) => {
+                            // A movie can't follow the machine jumping to a different point
+                            stop_movies(&mut recorder, &mut player, frame, &options);
+                            overlay.show_message(&format!("State loaded from slot {}", slot));
+                        },
+                        Err(e) => overlay.show_message(&e),
+                    },
+                    MenuAction::ToggleDisplayWait => {
+                        let enabled = !session.chip8.display_wait();
 
-                        if key == Keycode::LCtrl {
-                            keys_down[Keys::CTRL as usize] = true;
-                        }
-                        else if key == Keycode::R {
-                            keys_down[Keys::R as usize] = true;
+                        stop_movies(&mut recorder, &mut player, frame, &options);
+                        session.chip8.set_display_wait(enabled);
+                        session.header.display_wait = enabled;
+                    },
+                    MenuAction::AdjustSpeed(steps) => {
+                        match session.adjust_speed(steps) {
+                            Ok(_) => stop_movies(&mut recorder, &mut player, frame, &options),
+                            Err(e) => overlay.show_message(&e),
                         }
-                        
                     },
-                    Event::KeyUp {keycode: Some(key), ..} => {
-                        if let Some(k) = process_keycode(key) {
-                            chip8.key_up(k);
+                    MenuAction::NextTheme => {
+                        session.next_theme();
+                    },
+                    MenuAction::NextPersistence => {
+                        phosphor.mode = phosphor.mode.next();
+                        phosphor.update(session.chip8.get_video_memory());
+                    },
+                    MenuAction::ToggleEffects => renderer.effects.enabled = !renderer.effects.enabled,
+                    MenuAction::NextScaling => renderer.scaling = renderer.scaling.next(),
+                    MenuAction::NextWaveform => {
+                        sound.waveform = sound.waveform.next();
+                        device.lock().settings = sound;
+                        save_sound(&sound);
+                    },
+                    MenuAction::AdjustPitch(steps) => {
+                        sound.frequency = (sound.frequency + steps as f32 * PITCH_STEP).clamp(beeper::MIN_FREQUENCY, beeper::MAX_FREQUENCY);
+                        device.lock().settings = sound;
+                        save_sound(&sound);
+                    },
+                    MenuAction::AdjustVolume(steps) => {
+                        sound.volume = (sound.volume + steps as f32 * VOLUME_STEP).clamp(0.0, 1.0);
+                        device.lock().settings = sound;
+                        save_sound(&sound);
+                    },
+                    MenuAction::Quit => break 'running,
+                }
+            }
+
+            device.lock().set_gate(false);
+
+            renderer.draw(&mut canvas, &phosphor, &session.palette);
+            overlay.draw(&mut canvas, &settings(&session, &phosphor, &renderer, &sound));
+
+            // Presenting waits for vsync, which paces the loop
+            canvas.present();
+            scheduler.reset();
+            continue;
+        }
+
+        // While stepping, input is still collected so it is applied on the next tick, but nothing else runs until then
+        if stepping && !advance {
+            for event in event_pump.poll_iter() {
+                if gamepads.handle_event(&event, &mut holds, &mut input) {
+                    continue;
+                }
+
+                match event {
+                    Event::Quit {..} => break 'running,
+                    Event::Window {win_event: WindowEvent::SizeChanged(..), ..} => geometry.update(canvas.window()),
+                    Event::KeyDown {keycode: Some(key), repeat: false, ..} => match key {
+                        Keycode::P => stepping = false,
+                        Keycode::N => advance = true,
+                        // Keys held now would never see their release while the menu is open, so they are let go first
+                        Keycode::Escape => {
+                            keys_down = Default::default();
+                            release_keys(&session.chip8, &mut holds, &mut input);
+
+                            overlay.open_menu();
+                        },
+                        _ => {
+                            if let Some(k) = process_keycode(key) {
+                                holds.press(k, &mut input);
+                            }
+                        },
+                    },
+                    Event::KeyUp {keycode: Some(key), ..} => key_up(key, &mut keys_down, &mut fast_forward, &mut scheduler, &mut holds, &mut input),
+                    _ => {},
+                }
+            }
+
+            overlay.set_status(speed_status(stepping, fast_forward, speed));
+
+            if stepping && !advance {
+                device.lock().set_gate(false);
+
+                renderer.draw(&mut canvas, &phosphor, &session.palette);
+                overlay.draw(&mut canvas, &settings(&session, &phosphor, &renderer, &sound));
+
+                // Presenting waits for vsync, which paces the loop
+                canvas.present();
+                scheduler.reset();
+                continue;
+            }
+        }
+
+        // Set when the window changes size, as the screen then has to be drawn again to fill it
+        let mut window_changed = false;
+
+        // Set when something shown has changed and the screen needs drawing again
+        let mut redraw = false;
+
+        // Runs the 60 Hz timer ticks that have come due since the last time round. Each one runs the CPU
+        // for a tick's worth of cycles and then decrements the timers, whatever rate the display runs at
+        let due = if advance { 1 } else { scheduler.due() };
+        advance = false;
+
+        for _ in 0..due {
+            // A ROM picked from the browser or dropped onto the window, switched to once this round of events has been handled
+            let mut next_rom: Option<RomChoice> = None;
+            let mut switched = false;
+
+            // Runs instructions until they have used up the time that would pass in a frame
+            let mut tick: u32 = 0;
+            let mut poll: u32 = 0;
+
+            while !session.chip8.frame_complete() {
+                // Input is only read at the poll points spread through the frame, rather than between every instruction
+                if poll < polls_per_frame && session.chip8.frame_cycles() >= session.chip8.timing().frame_budget() * poll / polls_per_frame {
+                    poll += 1;
+                    input_point += 1;
+
+                    for event in event_pump.poll_iter() {
+                        if gamepads.handle_event(&event, &mut holds, &mut input) {
+                            continue;
                         }
 
-                        if key == Keycode::LCtrl {
-                            keys_down[Keys::CTRL as usize] = false;
+                        match event {
+                            Event::Quit {..} => {
+                                break 'running;
+                            },
+                            Event::Window {win_event: WindowEvent::SizeChanged(..), ..} => {
+                                geometry.update(canvas.window());
+                                window_changed = true;
+                            },
+                            Event::Window {win_event: WindowEvent::Moved(..), ..} => {
+                                geometry.update(canvas.window());
+                            },
+                            Event::DropFile {filename, ..} => {
+                                next_rom = Some(RomChoice::file(&filename));
+                            },
+                            Event::KeyDown {keycode: Some(key), repeat: false, ..} => {
+                                if let Some(k) = process_keycode(key) {
+                                    holds.press(k, &mut input);
+                                }
+
+                                if key == Keycode::LCtrl {
+                                    keys_down[Keys::Ctrl as usize] = true;
+                                }
+                                else if key == Keycode::LShift {
+                                    keys_down[Keys::Shift as usize] = true;
+                                }
+                                else if key == Keycode::LAlt || key == Keycode::RAlt {
+                                    keys_down[Keys::Alt as usize] = true;
+                                }
+                                else if key == Keycode::R {
+                                    keys_down[Keys::R as usize] = true;
+                                }
+
+                                // Ctrl+R restarts the ROM, Ctrl+Shift+R also clears RAM
+                                if keys_down[Keys::Ctrl as usize] && keys_down[Keys::R as usize] {
+                                    if keys_down[Keys::Shift as usize] {
+                                        input.push(InputEvent::Reset(ResetKind::Hard));
+                                    }
+                                    else {
+                                        input.push(InputEvent::Reset(ResetKind::Soft));
+                                    }
+                                }
+
+                                // Escape pauses and opens the menu. Keys held now would never see their release, so they are let go first
+                                if key == Keycode::Escape {
+                                    keys_down = Default::default();
+                                    release_keys(&session.chip8, &mut holds, &mut input);
+
+                                    overlay.open_menu();
+                                    break;
+                                }
+
+                                // Alt+Enter switches between a window and fullscreen
+                                if keys_down[Keys::Alt as usize] && (key == Keycode::Return || key == Keycode::KpEnter) {
+                                    geometry.update(canvas.window());
+                                    geometry::toggle_fullscreen(canvas.window_mut());
+                                }
+
+                                // P pauses for stepping a tick at a time with N. Tab fast forwards while it is held,
+                                // and [ and ] change the speed
+                                match key {
+                                    Keycode::P => {
+                                        stepping = true;
+                                        break;
+                                    },
+                                    Keycode::Tab => {
+                                        fast_forward = true;
+                                        scheduler.set_uncapped(true);
+                                    },
+                                    Keycode::LeftBracket | Keycode::RightBracket => {
+                                        speed = if key == Keycode::LeftBracket { speed.saturating_sub(1) } else { (speed + 1).min(SPEEDS.len() - 1) };
+                                        scheduler.set_speed(SPEEDS[speed]);
+                                    },
+                                    _ => {},
+                                }
+
+                                overlay.set_status(speed_status(stepping, fast_forward, speed));
+
+                                // - and = run fewer or more instructions each frame
+                                if matches!(key, Keycode::Minus | Keycode::KpMinus | Keycode::Equals | Keycode::KpPlus) {
+                                    let steps = if matches!(key, Keycode::Minus | Keycode::KpMinus) { -1 } else { 1 };
+
+                                    match session.adjust_speed(steps) {
+                                        Ok(instructions_per_frame) => {
+                                            stop_movies(&mut recorder, &mut player, frame, &options);
+                                            overlay.show_message(&format!("CPU speed: {} per frame", instructions_per_frame));
+                                        },
+                                        Err(e) => overlay.show_message(&e),
+                                    }
+                                }
+
+                                // F2 switches to the next colour theme
+                                if key == Keycode::F2 {
+                                    let theme = session.next_theme();
+
+                                    overlay.show_message(&format!("Theme: {}", theme));
+                                }
+
+                                // F12 saves a screenshot
+                                if key == Keycode::F12 {
+                                    match screenshot::capture(&session.rom.file_name_stem(), session.chip8.get_video_memory(), &session.palette, screenshot_scale) {
+                                        Ok(path) => overlay.show_message(&format!("Screenshot saved to {}", path.display())),
+                                        Err(e) => overlay.show_message(&e),
+                                    }
+                                }
+
+                                // F9 records a GIF and F10 records frames and sound, pressing either again stops
+                                if key == Keycode::F9 || key == Keycode::F10 {
+                                    if let Some(recording) = capture.take() {
+                                        stop_capture(recording, &mut overlay);
+                                    }
+                                    else {
+                                        let kind = if key == Keycode::F9 { CaptureKind::Gif } else { CaptureKind::Frames };
+
+                                        match Capture::start(kind, &session.rom.file_name_stem(), session.chip8.get_video_memory(), &session.palette, capture_scale, sound) {
+                                            Ok(recording) => {
+                                                overlay.show_message(&format!("Recording to {}", recording.path()));
+                                                capture = Some(recording);
+                                            },
+                                            Err(e) => overlay.show_message(&e),
+                                        }
+                                    }
+                                }
+
+                                // Ctrl+O opens the ROM browser. Keys held now would never see their release while it is open, so they are let go first
+                                if keys_down[Keys::Ctrl as usize] && key == Keycode::O {
+                                    keys_down = Default::default();
+                                    release_keys(&session.chip8, &mut holds, &mut input);
+
+                                    match run_browser(&mut RomBrowser::for_folders(&rom_folders, &recent), &mut event_pump, &mut canvas, &mut gamepads, &mut holds, &mut input) {
+                                        BrowserResult::Chosen(rom) => next_rom = Some(rom),
+                                        BrowserResult::Cancelled => {
+                                            renderer.draw(&mut canvas, &phosphor, &session.palette);
+                                            canvas.present();
+                                        },
+                                        BrowserResult::Quit => break 'running,
+                                    }
+
+                                    // Time spent in the browser isn't made up for afterwards
+                                    scheduler.reset();
+
+                                    // The browser took over the event loop, so this frame's event list is no longer valid
+                                    break;
+                                }
+                            },
+                            Event::KeyUp {keycode: Some(key), ..} => {
+                                key_up(key, &mut keys_down, &mut fast_forward, &mut scheduler, &mut holds, &mut input);
+                                overlay.set_status(speed_status(stepping, fast_forward, speed));
+                            },
+                            _ => {},
                         }
-                        else if key == Keycode::R {
-                            keys_down[Keys::R as usize] = false;
+                    }
+
+                    queue.schedule(input_point, input.drain(..));
+                    queue.take_due(input_point, &mut input);
+                }
+
+                // A ROM that can't be loaded leaves the current one to carry on with the frame as if nothing happened
+                if let Some(rom) = next_rom.take() {
+                    match Session::start(&rom, &options, &config, None, seed) {
+                        Ok(new_session) => {
+                            // Movies only cover a single ROM
+                            stop_movies(&mut recorder, &mut player, frame, &options);
+
+                            session = new_session;
+                            phosphor.clear();
+                            recent.add(&session.rom);
+                            input.clear();
+                            queue.clear();
+                            device.lock().set_gate(false);
+
+                            println!("Loaded {}", rom.path);
+
+                            switched = true;
+                            break;
+                        },
+                        Err(e) => println!("Unable to load {}: {}", rom.path, e),
+                    }
+                }
+
+                // While a movie is playing it is the only source of input, so that the run can't diverge from the recording
+                if let Some(movie) = &mut player {
+                    input.clear();
+
+                    while let Some(event) = movie.next_event(frame, tick) {
+                        input.push(event);
+                    }
+                }
+
+                for event in input.drain(..) {
+                    if let Some(movie) = &mut recorder {
+                        if let Err(e) = movie.record(frame, tick, &event) {
+                            println!("Unable to write to movie file: {}. Recording stopped", e);
+                            recorder = None;
                         }
-                    },
-                    _ => {},
+                    }
+
+                    event.apply(&mut session.chip8);
                 }
+
+                session.chip8.tick();
+                tick += 1;
             }
 
-            if keys_down[Keys::CTRL as usize] && keys_down[Keys::R as usize] {
-                chip8.reset();
-                draw(&chip8, &mut canvas);
+            // The new ROM starts on a frame of its own
+            if switched {
+                continue 'running;
             }
 
-            if !chip8.has_done_reset()
-            {
-                chip8.tick();
+            session.chip8.update_timers();
+
+            frame += 1;
+
+            overlay.record_frame(tick, session.chip8.waiting_for_key().is_some());
+
+            // A movie can't follow the ROM changing under it
+            if session.poll_reload() {
+                stop_movies(&mut recorder, &mut player, frame, &options);
             }
-        }
 
-        chip8.update_timers();
+            if player.as_ref().is_some_and(|movie| movie.is_finished(frame)) {
+                println!("Movie playback finished");
+                player = None;
+            }
+
+            // Fading pixels change what is shown even on ticks where the CHIP-8 didn't draw anything
+            redraw |= phosphor.update(session.chip8.get_video_memory());
+            redraw |= session.chip8.get_screen_update_status();
+
+            let beeping = session.chip8.should_beep();
 
-        let should_draw = chip8.get_screen_update_status();
+            if let Some(recording) = &mut capture {
+                if let Err(e) = recording.record_frame(session.chip8.get_video_memory(), beeping) {
+                    overlay.show_message(&e);
+                    capture = None;
+                }
+            }
 
-        if should_draw {
-            draw(&chip8, &mut canvas);
+            device.lock().set_gate(beeping);
+
+            // The menu was opened or stepping started during this tick, so the rest wait until it is done
+            if overlay.is_paused() || stepping || scheduler.out_of_time() {
+                break;
+            }
         }
 
-        if chip8.should_beep() {
-            device.resume();
+        let overlay_changed = overlay.needs_redraw();
+
+        if redraw || window_changed || overlay_changed {
+            renderer.draw(&mut canvas, &phosphor, &session.palette);
+            overlay.draw(&mut canvas, &settings(&session, &phosphor, &renderer, &sound));
+            canvas.present();
         }
-        else {
-            device.pause();
+
+        scheduler.wait();
+    }
+
+    if let Some(movie) = recorder {
+        finish_recording(movie, frame, &options);
+    }
+
+    if let Some(recording) = capture {
+        stop_capture(recording, &mut overlay);
+    }
+
+    geometry.update(canvas.window());
+    geometry.save(geometry::GEOMETRY_PATH);
+}
+
+// Keeps sound changes made in the menu for next time. Not being able to save them only means they have to be made again
+fn save_sound(sound: &BeeperSettings) {
+    if let Err(e) = sound.store(std::path::Path::new(config::CONFIG_PATH)) {
+        println!("{}", e);
+    }
+}
+
+// Handles a key being let go, wherever the events are being read, so no release is missed
+fn key_up(key: Keycode, keys_down: &mut [bool; 4], fast_forward: &mut bool, scheduler: &mut Scheduler, holds: &mut KeyHolds, input: &mut Vec<InputEvent>) {
+    if let Some(k) = process_keycode(key) {
+        holds.release(k, input);
+    }
+
+    if key == Keycode::Tab {
+        *fast_forward = false;
+        scheduler.set_uncapped(false);
+    }
+
+    if key == Keycode::LCtrl {
+        keys_down[Keys::Ctrl as usize] = false;
+    }
+    else if key == Keycode::LShift {
+        keys_down[Keys::Shift as usize] = false;
+    }
+    else if key == Keycode::LAlt || key == Keycode::RAlt {
+        keys_down[Keys::Alt as usize] = false;
+    }
+    else if key == Keycode::R {
+        keys_down[Keys::R as usize] = false;
+    }
+}
+
+// Lets go of every CHIP-8 key that is held, or will be once the input waiting to be applied has been
+fn release_keys(chip8: &Chip8, holds: &mut KeyHolds, input: &mut Vec<InputEvent>) {
+    holds.clear();
+
+    let mut held: [bool; 16] = std::array::from_fn(|k| chip8.is_key_down(k));
+
+    for event in input.iter() {
+        match *event {
+            InputEvent::KeyDown(k) if k < 16 => held[k] = true,
+            InputEvent::KeyUp(k) if k < 16 => held[k] = false,
+            _ => {},
         }
+    }
+
+    for (k, _) in held.iter().enumerate().filter(|(_, &down)| down) {
+        input.push(InputEvent::KeyUp(k));
+    }
+}
+
+// What the overlay shows while the emulator isn't running at normal speed
+fn speed_status(stepping: bool, fast_forward: bool, speed: usize) -> Option<String> {
+    if stepping {
+        Some("Paused - N: next tick, P: resume".to_string())
+    }
+    else if fast_forward {
+        Some("Fast forward".to_string())
+    }
+    else if speed != NORMAL_SPEED {
+        Some(format!("Speed {}%", (SPEEDS[speed] * 100.0).round()))
+    }
+    else {
+        None
+    }
+}
+
+fn stop_capture(recording: Capture, overlay: &mut Overlay) {
+    let path = recording.path().to_string();
+
+    match recording.finish() {
+        Ok(()) => overlay.show_message(&format!("Recording saved to {}", path)),
+        Err(e) => overlay.show_message(&e),
+    }
+}
+
+fn finish_recording(movie: MovieRecorder, frame: u64, options: &Options) {
+    match movie.finish(frame) {
+        Ok(()) => println!("Movie saved to {}", options.record_path.as_deref().unwrap_or_default()),
+        Err(e) => println!("Unable to finish movie file: {}", e),
+    }
+}
+
+fn stop_movies(recorder: &mut Option<MovieRecorder>, player: &mut Option<MoviePlayer>, frame: u64, options: &Options) {
+    if let Some(movie) = recorder.take() {
+        finish_recording(movie, frame, options);
+    }
+
+    if player.take().is_some() {
+        println!("Movie playback stopped");
+    }
+}
+
+enum BrowserResult {
+    Chosen(RomChoice),
+    Cancelled,
+    Quit,
+}
 
-        let seconds = time.elapsed().as_secs_f64();
+// Shows the browser until a ROM is chosen, it is closed, or the window is closed
+// Controllers are still looked after while the browser is open, so ones plugged in now work
+// and buttons let go of now don't stay held
+fn run_browser(browser: &mut RomBrowser, event_pump: &mut EventPump, canvas: &mut Canvas<Window>, gamepads: &mut Gamepads, holds: &mut KeyHolds, input: &mut Vec<InputEvent>) -> BrowserResult {
+    loop {
+        for event in event_pump.poll_iter() {
+            if gamepads.handle_event(&event, holds, input) {
+                continue;
+            }
 
-        // If it has taken less time than it should to run the instructions in this frame
-        // Then wait for the remaining time so that it is accurate to the number of instructions that should run
-        if WAIT_TIME > seconds {
-            std::thread::sleep(Duration::from_secs_f64(WAIT_TIME - seconds));
+            match browser.handle_event(&event) {
+                BrowserAction::Load(rom) => return BrowserResult::Chosen(rom),
+                BrowserAction::Cancel => return BrowserResult::Cancelled,
+                BrowserAction::Quit => return BrowserResult::Quit,
+                BrowserAction::None => {},
+            }
         }
+
+        // Presenting waits for vsync, which paces the loop
+        browser.draw(canvas);
     }
 }
 
@@ -196,21 +940,14 @@ fn process_keycode(key: Keycode) -> Option<usize> {
     }
 }
 
-fn draw(chip8: &Chip8, canvas: &mut Canvas<Window>) {
-    canvas.set_draw_color(Color::RGB(0, 0, 0));
-    canvas.clear();
-
-    let chip8_screen = chip8.get_video_memory();
-
-    canvas.set_draw_color(Color::RGB(255, 255, 255));
-
-    for (y, row) in chip8_screen.iter().enumerate() {
-        for (x, &_col) in row.iter().enumerate() {
-            if chip8_screen[y][x] {
-                let _ = canvas.fill_rect(Rect::new((x as u32 * WINDOW_SCALE) as i32, (y as u32 * WINDOW_SCALE) as i32, WINDOW_SCALE, WINDOW_SCALE));
-            }
-        }
+fn settings(session: &Session, phosphor: &Phosphor, renderer: &Renderer, sound: &BeeperSettings) -> Settings {
+    Settings {
+        display_wait: session.chip8.display_wait(),
+        timing: session.chip8.timing(),
+        theme: session.theme_name(),
+        persistence: phosphor.mode,
+        effects: renderer.effects.enabled,
+        scaling: renderer.scaling,
+        sound: *sound,
     }
-
-    canvas.present();
-}
\ No newline at end of file
+}
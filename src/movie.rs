@@ -0,0 +1,220 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+
+use crate::chip8::{KeyWait, ResetKind};
+use crate::input::InputEvent;
+use crate::timing::TimingMode;
+
+const MOVIE_MAGIC: &str = "chip8-movie";
+// Bumped whenever the format or the emulation changes in a way that would stop older movies playing back the same
+const MOVIE_VERSION: u32 = 4;
+
+// FNV-1a, used to check a movie is being played back against the ROM it was recorded with
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    let mut hash: u64 = 0xCBF2_9CE4_8422_2325;
+
+    for &byte in rom {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01B3);
+    }
+
+    hash
+}
+
+// Everything besides the input that affects how a run plays out
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct MovieHeader {
+    pub rom_hash: u64,
+    pub timing: TimingMode,
+    pub load_address: u16,
+    pub display_wait: bool,
+    pub key_wait: KeyWait,
+    pub seed: Option<u64>,
+}
+
+// Movies are plain text so they can be read and attached to bug reports as is:
+//
+//   chip8-movie 4
+//   rom 1a2b3c4d5e6f7a8b
+//   timing fixed 8
+//   load-address 0x200
+//   display-wait
+//   key-wait press
+//   seed 12345
+//   <frame> <tick> down <key>
+//   <frame> <tick> up <key>
+//   <frame> <tick> reset soft|hard
+//   end <frame>
+//
+// where tick is the index of the instruction within the frame that the event was applied before
+pub struct MovieRecorder {
+    writer: BufWriter<File>,
+}
+
+impl MovieRecorder {
+    pub fn create(path: &str, header: &MovieHeader) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        writeln!(writer, "{} {}", MOVIE_MAGIC, MOVIE_VERSION)?;
+        writeln!(writer, "rom {:016x}", header.rom_hash)?;
+        writeln!(writer, "timing {}", header.timing)?;
+        writeln!(writer, "load-address {:#05X}", header.load_address)?;
+
+        if header.display_wait {
+            writeln!(writer, "display-wait")?;
+        }
+
+        // Waiting for a release is the default, so the line is only written for a press
+        if header.key_wait != KeyWait::Release {
+            writeln!(writer, "key-wait {}", header.key_wait)?;
+        }
+
+        if let Some(seed) = header.seed {
+            writeln!(writer, "seed {}", seed)?;
+        }
+
+        Ok(MovieRecorder { writer })
+    }
+
+    pub fn record(&mut self, frame: u64, tick: u32, event: &InputEvent) -> io::Result<()> {
+        match *event {
+            InputEvent::KeyDown(key) => writeln!(self.writer, "{} {} down {:X}", frame, tick, key),
+            InputEvent::KeyUp(key) => writeln!(self.writer, "{} {} up {:X}", frame, tick, key),
+            InputEvent::Reset(ResetKind::Soft) => writeln!(self.writer, "{} {} reset soft", frame, tick),
+            InputEvent::Reset(ResetKind::Hard) => writeln!(self.writer, "{} {} reset hard", frame, tick),
+        }
+    }
+
+    pub fn finish(mut self, frame: u64) -> io::Result<()> {
+        writeln!(self.writer, "end {}", frame)?;
+        self.writer.flush()
+    }
+}
+
+pub struct MoviePlayer {
+    header: MovieHeader,
+    events: Vec<(u64, u32, InputEvent)>,
+    next: usize,
+    end_frame: u64,
+}
+
+impl MoviePlayer {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Unable to read {}: {}", path, e))?;
+        let mut lines = text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty());
+
+        let err = |line_num: usize, msg: &str| format!("{}:{}: {}", path, line_num + 1, msg);
+
+        match lines.next() {
+            Some((_, line)) if line.trim() == format!("{} {}", MOVIE_MAGIC, MOVIE_VERSION) => {},
+            _ => return Err(format!("{} is not a version {} movie file", path, MOVIE_VERSION)),
+        }
+
+        let mut rom_hash = None;
+        let mut timing = None;
+        let mut display_wait = false;
+        let mut key_wait = KeyWait::Release;
+        let mut load_address = None;
+        let mut seed = None;
+
+        let mut events = Vec::new();
+        let mut end_frame = None;
+
+        for (line_num, line) in lines {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+
+            match fields.as_slice() {
+                ["rom", hash] => {
+                    rom_hash = Some(u64::from_str_radix(hash, 16).map_err(|_| err(line_num, "invalid ROM hash"))?);
+                },
+                ["timing", ..] => {
+                    timing = Some(line.trim()["timing".len()..].parse().map_err(|e: String| err(line_num, &e))?);
+                },
+                ["display-wait"] => {
+                    display_wait = true;
+                },
+                ["load-address", value] => {
+                    load_address = Some(crate::parse_address(value).map_err(|e| err(line_num, &e))?);
+                },
+                ["key-wait", value] => {
+                    key_wait = value.parse().map_err(|e: String| err(line_num, &e))?;
+                },
+                ["seed", value] => {
+                    seed = Some(value.parse().map_err(|_| err(line_num, "invalid seed"))?);
+                },
+                ["end", frame] => {
+                    end_frame = Some(frame.parse().map_err(|_| err(line_num, "invalid end frame"))?);
+                },
+                [frame, tick, kind, rest @ ..] => {
+                    let frame: u64 = frame.parse().map_err(|_| err(line_num, "invalid frame number"))?;
+                    let tick: u32 = tick.parse().map_err(|_| err(line_num, "invalid tick number"))?;
+
+                    let key = || match rest {
+                        [key] => usize::from_str_radix(key, 16).ok().filter(|&k| k < 16).ok_or_else(|| err(line_num, "invalid key")),
+                        _ => Err(err(line_num, "expected a single key")),
+                    };
+
+                    let event = match *kind {
+                        "down" => InputEvent::KeyDown(key()?),
+                        "up" => InputEvent::KeyUp(key()?),
+                        "reset" => match rest {
+                            ["soft"] => InputEvent::Reset(ResetKind::Soft),
+                            ["hard"] => InputEvent::Reset(ResetKind::Hard),
+                            _ => return Err(err(line_num, "expected a soft or hard reset")),
+                        },
+                        _ => return Err(err(line_num, "unknown event")),
+                    };
+
+                    if let Some(&(last_frame, last_tick, _)) = events.last() {
+                        if (frame, tick) < (last_frame, last_tick) {
+                            return Err(err(line_num, "events are out of order"));
+                        }
+                    }
+
+                    events.push((frame, tick, event));
+                },
+                _ => return Err(err(line_num, "unrecognised line")),
+            }
+        }
+
+        // A movie that was cut short (e.g. the emulator crashed while recording) plays until its last event
+        let end_frame = end_frame.unwrap_or_else(|| events.last().map_or(0, |&(frame, _, _)| frame + 1));
+
+        let header = MovieHeader {
+            rom_hash: rom_hash.ok_or_else(|| format!("{} has no ROM hash", path))?,
+            timing: timing.ok_or_else(|| format!("{} has no timing mode", path))?,
+            load_address: load_address.ok_or_else(|| format!("{} has no load address", path))?,
+            display_wait,
+            key_wait,
+            seed,
+        };
+
+        Ok(MoviePlayer {
+            header,
+            events,
+            next: 0,
+            end_frame,
+        })
+    }
+
+    pub fn header(&self) -> &MovieHeader {
+        &self.header
+    }
+
+    // Returns the next event due at or before the given point in the run
+    pub fn next_event(&mut self, frame: u64, tick: u32) -> Option<InputEvent> {
+        let &(event_frame, event_tick, event) = self.events.get(self.next)?;
+
+        if (event_frame, event_tick) <= (frame, tick) {
+            self.next += 1;
+            Some(event)
+        }
+        else {
+            None
+        }
+    }
+
+    pub fn is_finished(&self, frame: u64) -> bool {
+        self.next >= self.events.len() && frame >= self.end_frame
+    }
+}
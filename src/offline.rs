@@ -0,0 +1,121 @@
+use crate::beeper::{self, Beeper, BeeperSettings};
+use crate::config::Config;
+use crate::loader::RomChoice;
+use crate::movie::MoviePlayer;
+use crate::session::Session;
+use crate::wav::WavWriter;
+use crate::Options;
+
+const FRAME_RATE: usize = 60;
+
+// Runs a ROM as fast as possible without a window or sound device, writing what the beeper would have
+// played to a WAV file. Input comes from the movie if there is one, otherwise no keys are pressed.
+// It stops after the given number of frames, or when the movie ends
+pub fn render_audio(path: &str, rom: &RomChoice, options: &Options, config: &Config, mut player: Option<MoviePlayer>, seed: u64, frames: Option<u64>) -> Result<u64, String> {
+    if frames.is_none() && player.is_none() {
+        return Err("--render-audio needs --frames or --play to know when to stop".to_string());
+    }
+
+    let mut session = Session::start(rom, options, config, player.as_ref().map(|movie| movie.header()), seed)?;
+
+    if let Some(player) = &player {
+        if player.header().rom_hash != session.header.rom_hash {
+            println!("Warning: the movie was recorded with a different ROM and will likely desync");
+        }
+    }
+
+    let mut wav = WavWriter::create(path, beeper::SAMPLE_RATE as u32)?;
+    let mut beeper = Beeper::new(BeeperSettings::from_config(config), beeper::SAMPLE_RATE);
+    let mut samples = vec![0.0; beeper::SAMPLE_RATE as usize / FRAME_RATE];
+    let mut frame: u64 = 0;
+
+    while frames.is_none_or(|frames| frame < frames) {
+        if player.as_ref().is_some_and(|movie| movie.is_finished(frame)) {
+            player = None;
+
+            if frames.is_none() {
+                break;
+            }
+        }
+
+        let mut tick: u32 = 0;
+
+        while !session.chip8.frame_complete() {
+            if let Some(movie) = &mut player {
+                while let Some(event) = movie.next_event(frame, tick) {
+                    event.apply(&mut session.chip8);
+                }
+            }
+
+            session.chip8.tick();
+            tick += 1;
+        }
+
+        session.chip8.update_timers();
+
+        beeper.set_gate(session.chip8.should_beep());
+
+        for sample in samples.iter_mut() {
+            *sample = beeper.next_sample();
+        }
+
+        wav.write(&samples)?;
+        frame += 1;
+    }
+
+    wav.finish()?;
+
+    Ok(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn beep_length_follows_the_sound_timer() {
+        let dir = std::env::temp_dir();
+        let rom_path = dir.join(format!("chip8-offline-{}.ch8", std::process::id()));
+        let wav_path = dir.join(format!("chip8-offline-{}.wav", std::process::id()));
+
+        // V0 = 6, ST = V0, then jumps to itself forever
+        fs::write(&rom_path, [0x60, 0x06, 0xF0, 0x18, 0x12, 0x04]).unwrap();
+
+        let options = Options {
+            rom_path: None,
+            record_path: None,
+            play_path: None,
+            seed: None,
+            timing: None,
+            display_wait: false,
+            watch: false,
+            keep_registers: false,
+            load_address: None,
+            render_audio: None,
+            frames: None,
+        };
+
+        let rom = RomChoice::file(&rom_path.to_string_lossy());
+        let rendered = render_audio(&wav_path.to_string_lossy(), &rom, &options, &Config::default(), None, 0, Some(30));
+        let bytes = fs::read(&wav_path).unwrap();
+
+        fs::remove_file(&rom_path).unwrap();
+        fs::remove_file(&wav_path).unwrap();
+
+        assert_eq!(rendered, Ok(30));
+
+        let samples: Vec<i16> = bytes[44..].chunks_exact(2).map(|pair| i16::from_le_bytes([pair[0], pair[1]])).collect();
+        let frame_samples = beeper::SAMPLE_RATE as usize / FRAME_RATE;
+        assert_eq!(samples.len(), 30 * frame_samples);
+
+        // The timer is set and then counts down within the first frame, so the gate is open for 5 frames and
+        // the envelope adds a short fade out after that
+        let first = samples.iter().position(|&s| s != 0).unwrap();
+        let last = samples.iter().rposition(|&s| s != 0).unwrap();
+        let span = last - first + 1;
+
+        assert_eq!(first, 0);
+        assert!(span > 5 * frame_samples && span < 6 * frame_samples, "beeped for {} samples", span);
+    }
+}
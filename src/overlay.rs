@@ -0,0 +1,377 @@
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas};
+use sdl2::video::Window;
+
+use std::time::{Duration, Instant};
+
+use crate::beeper::BeeperSettings;
+use crate::phosphor::Persistence;
+use crate::renderer::Scaling;
+use crate::timing::TimingMode;
+use crate::text::{self, CELL_HEIGHT};
+
+const TEXT_SCALE: u32 = 3;
+const MARGIN: i32 = 16;
+const ROW_HEIGHT: i32 = ((CELL_HEIGHT + 2) * TEXT_SCALE) as i32;
+
+const MESSAGE_DURATION: Duration = Duration::from_secs(2);
+const STATE_SLOTS: u32 = 10;
+
+const SHADE: Color = Color::RGBA(0, 0, 0, 200);
+const TEXT: Color = Color::RGB(220, 220, 220);
+const HIGHLIGHT: Color = Color::RGB(255, 200, 60);
+
+// The current values of everything on the settings page, so they can be shown next to each item
+pub struct Settings {
+    pub display_wait: bool,
+    pub timing: TimingMode,
+    pub theme: &'static str,
+    pub persistence: Persistence,
+    pub effects: bool,
+    pub scaling: Scaling,
+    pub sound: BeeperSettings,
+}
+
+pub enum MenuAction {
+    None,
+    Reset,
+    SaveState(u32),
+    LoadState(u32),
+    ToggleDisplayWait,
+    // Positive to speed up, negative to slow down
+    AdjustSpeed(i32),
+    NextTheme,
+    NextPersistence,
+    ToggleEffects,
+    NextScaling,
+    NextWaveform,
+    // Positive to raise, negative to lower
+    AdjustPitch(i32),
+    AdjustVolume(i32),
+    Quit,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum MenuPage {
+    Main,
+    Settings,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum MenuItem {
+    Resume,
+    Reset,
+    SaveState,
+    LoadState,
+    Settings,
+    Quit,
+    DisplayWait,
+    Speed,
+    Theme,
+    Persistence,
+    Effects,
+    Scaling,
+    Waveform,
+    Pitch,
+    Volume,
+    ShowFps,
+    Back,
+}
+
+const MAIN_ITEMS: [MenuItem; 6] = [
+    MenuItem::Resume,
+    MenuItem::Reset,
+    MenuItem::SaveState,
+    MenuItem::LoadState,
+    MenuItem::Settings,
+    MenuItem::Quit,
+];
+
+const SETTINGS_ITEMS: [MenuItem; 11] = [
+    MenuItem::DisplayWait,
+    MenuItem::Speed,
+    MenuItem::Theme,
+    MenuItem::Persistence,
+    MenuItem::Effects,
+    MenuItem::Scaling,
+    MenuItem::Waveform,
+    MenuItem::Pitch,
+    MenuItem::Volume,
+    MenuItem::ShowFps,
+    MenuItem::Back,
+];
+
+// Everything drawn over the emulated screen: the pause menu, short status messages and the speed counter.
+// It is drawn onto the canvas after the CHIP-8 framebuffer, which it never touches
+pub struct Overlay {
+    page: Option<MenuPage>,
+    selected: usize,
+    slot: u32,
+    message: Option<(String, Instant)>,
+    // Shown for as long as it is set, for things like the emulator running at a different speed
+    status: Option<String>,
+    show_fps: bool,
+    was_visible: bool,
+    sample_start: Instant,
+    sample_frames: u32,
+    sample_instructions: u64,
+    fps: f64,
+    ips: f64,
+    // Whether FX0A was waiting for a key at the end of the last frame
+    waiting_for_key: bool,
+}
+
+impl Overlay {
+    pub fn new(show_fps: bool) -> Self {
+        Overlay {
+            page: None,
+            selected: 0,
+            slot: 1,
+            message: None,
+            status: None,
+            show_fps,
+            was_visible: false,
+            sample_start: Instant::now(),
+            sample_frames: 0,
+            sample_instructions: 0,
+            fps: 0.0,
+            ips: 0.0,
+            waiting_for_key: false,
+        }
+    }
+
+    // Emulation is paused while the menu is open
+    pub fn is_paused(&self) -> bool {
+        self.page.is_some()
+    }
+
+    pub fn open_menu(&mut self) {
+        self.page = Some(MenuPage::Main);
+        self.selected = 0;
+    }
+
+    pub fn show_message(&mut self, message: &str) {
+        println!("{}", message);
+        self.message = Some((message.to_string(), Instant::now()));
+    }
+
+    pub fn set_status(&mut self, status: Option<String>) {
+        self.status = status;
+    }
+
+    // Called once per emulated frame with the number of instructions run in it
+    pub fn record_frame(&mut self, instructions: u32, waiting_for_key: bool) {
+        self.waiting_for_key = waiting_for_key;
+        self.sample_frames += 1;
+        self.sample_instructions += instructions as u64;
+
+        let elapsed = self.sample_start.elapsed().as_secs_f64();
+
+        if elapsed >= 1.0 {
+            self.fps = self.sample_frames as f64 / elapsed;
+            self.ips = self.sample_instructions as f64 / elapsed;
+
+            self.sample_start = Instant::now();
+            self.sample_frames = 0;
+            self.sample_instructions = 0;
+        }
+    }
+
+    // Whether the screen has to be redrawn this frame for the overlay, including once more after it disappears to clear it away
+    pub fn needs_redraw(&mut self) -> bool {
+        if self.message.as_ref().is_some_and(|(_, shown)| shown.elapsed() >= MESSAGE_DURATION) {
+            self.message = None;
+        }
+
+        let visible = self.page.is_some() || self.message.is_some() || self.status.is_some() || self.show_fps;
+        let needs_redraw = visible || self.was_visible;
+
+        self.was_visible = visible;
+        needs_redraw
+    }
+
+    fn items(&self) -> &'static [MenuItem] {
+        match self.page {
+            Some(MenuPage::Settings) => &SETTINGS_ITEMS,
+            _ => &MAIN_ITEMS,
+        }
+    }
+
+    // Handles an event while the menu is open
+    pub fn handle_event(&mut self, event: &Event) -> MenuAction {
+        let key = match event {
+            Event::Quit {..} => return MenuAction::Quit,
+            Event::KeyDown {keycode: Some(key), ..} => *key,
+            _ => return MenuAction::None,
+        };
+
+        let items = self.items();
+        let item = items[self.selected.min(items.len() - 1)];
+
+        match key {
+            Keycode::Escape => {
+                if self.page == Some(MenuPage::Settings) {
+                    self.page = Some(MenuPage::Main);
+                    self.selected = 0;
+                }
+                else {
+                    self.page = None;
+                }
+            },
+            Keycode::Up => self.selected = (self.selected + items.len() - 1) % items.len(),
+            Keycode::Down => self.selected = (self.selected + 1) % items.len(),
+            Keycode::Left if item == MenuItem::SaveState || item == MenuItem::LoadState => {
+                self.slot = if self.slot == 1 { STATE_SLOTS } else { self.slot - 1 };
+            },
+            Keycode::Right if item == MenuItem::SaveState || item == MenuItem::LoadState => {
+                self.slot = self.slot % STATE_SLOTS + 1;
+            },
+            Keycode::Left | Keycode::Right if item == MenuItem::Pitch || item == MenuItem::Volume => {
+                let steps = if key == Keycode::Left { -1 } else { 1 };
+
+                return if item == MenuItem::Pitch { MenuAction::AdjustPitch(steps) } else { MenuAction::AdjustVolume(steps) };
+            },
+            Keycode::Left | Keycode::Right if item == MenuItem::Speed => {
+                return MenuAction::AdjustSpeed(if key == Keycode::Left { -1 } else { 1 });
+            },
+            Keycode::Return | Keycode::KpEnter => return self.activate(item),
+            _ => {},
+        }
+
+        MenuAction::None
+    }
+
+    fn activate(&mut self, item: MenuItem) -> MenuAction {
+        match item {
+            MenuItem::Resume => {
+                self.page = None;
+                MenuAction::None
+            },
+            MenuItem::Reset => {
+                self.page = None;
+                MenuAction::Reset
+            },
+            MenuItem::SaveState => {
+                self.page = None;
+                MenuAction::SaveState(self.slot)
+            },
+            MenuItem::LoadState => {
+                self.page = None;
+                MenuAction::LoadState(self.slot)
+            },
+            MenuItem::Settings => {
+                self.page = Some(MenuPage::Settings);
+                self.selected = 0;
+                MenuAction::None
+            },
+            MenuItem::Quit => MenuAction::Quit,
+            MenuItem::DisplayWait => MenuAction::ToggleDisplayWait,
+            MenuItem::Theme => MenuAction::NextTheme,
+            MenuItem::Persistence => MenuAction::NextPersistence,
+            MenuItem::Effects => MenuAction::ToggleEffects,
+            MenuItem::Scaling => MenuAction::NextScaling,
+            MenuItem::Waveform => MenuAction::NextWaveform,
+            // These are changed with left and right
+            MenuItem::Speed | MenuItem::Pitch | MenuItem::Volume => MenuAction::None,
+            MenuItem::ShowFps => {
+                self.show_fps = !self.show_fps;
+                MenuAction::None
+            },
+            MenuItem::Back => {
+                self.page = Some(MenuPage::Main);
+                self.selected = 0;
+                MenuAction::None
+            },
+        }
+    }
+
+    fn label(&self, item: MenuItem, settings: &Settings) -> String {
+        let on_off = |on: bool| if on { "On" } else { "Off" };
+
+        match item {
+            MenuItem::Resume => "Resume".to_string(),
+            MenuItem::Reset => "Reset".to_string(),
+            MenuItem::SaveState => format!("Save state  < slot {} >", self.slot),
+            MenuItem::LoadState => format!("Load state  < slot {} >", self.slot),
+            MenuItem::Settings => "Settings".to_string(),
+            MenuItem::Quit => "Quit".to_string(),
+            MenuItem::DisplayWait => format!("Display wait: {}", on_off(settings.display_wait)),
+            MenuItem::Speed => match settings.timing {
+                TimingMode::Fixed(instructions_per_frame) => format!("CPU speed  < {} per frame >", instructions_per_frame),
+                TimingMode::CosmacVip => "CPU speed: VIP timing".to_string(),
+            },
+            MenuItem::Theme => format!("Theme: {}", settings.theme),
+            MenuItem::Persistence => format!("Persistence: {}", settings.persistence),
+            MenuItem::Effects => format!("CRT effects: {}", on_off(settings.effects)),
+            MenuItem::Scaling => format!("Scaling: {}", settings.scaling),
+            MenuItem::Waveform => format!("Beep sound: {}", settings.sound.waveform),
+            MenuItem::Pitch => format!("Beep pitch  < {:.0} Hz >", settings.sound.frequency),
+            MenuItem::Volume => format!("Beep volume  < {:.0}% >", settings.sound.volume * 100.0),
+            MenuItem::ShowFps => format!("Show FPS: {}", on_off(self.show_fps)),
+            MenuItem::Back => "Back".to_string(),
+        }
+    }
+
+    pub fn draw(&self, canvas: &mut Canvas<Window>, settings: &Settings) {
+        let (width, height) = canvas.output_size().unwrap_or((0, 0));
+
+        canvas.set_blend_mode(BlendMode::Blend);
+
+        if let Some(page) = self.page {
+            canvas.set_draw_color(SHADE);
+            let _ = canvas.fill_rect(None);
+
+            let title = match page {
+                MenuPage::Main => "Paused",
+                MenuPage::Settings => "Settings",
+            };
+
+            let items = self.items();
+            let top = (height as i32 - ROW_HEIGHT * (items.len() as i32 + 2)) / 2;
+
+            draw_centred(canvas, title, top, width, TEXT);
+
+            for (i, &item) in items.iter().enumerate() {
+                let colour = if i == self.selected { HIGHLIGHT } else { TEXT };
+                let label = self.label(item, settings);
+
+                draw_centred(canvas, &label, top + ROW_HEIGHT * (i as i32 + 2), width, colour);
+            }
+        }
+
+        if let Some(status) = &self.status {
+            draw_shaded(canvas, status, MARGIN, MARGIN);
+        }
+
+        if self.show_fps {
+            let waiting = if self.waiting_for_key { "  Waiting for key" } else { "" };
+            let counter = format!("{:.0} FPS  {:.0} IPS{}", self.fps, self.ips, waiting);
+            draw_shaded(canvas, &counter, width as i32 - MARGIN - text::text_width(&counter, TEXT_SCALE) as i32, MARGIN);
+        }
+
+        if let Some((message, _)) = &self.message {
+            draw_shaded(canvas, message, MARGIN, height as i32 - MARGIN - ROW_HEIGHT);
+        }
+
+        canvas.set_blend_mode(BlendMode::None);
+    }
+}
+
+fn draw_centred(canvas: &mut Canvas<Window>, line: &str, y: i32, width: u32, colour: Color) {
+    let x = (width as i32 - text::text_width(line, TEXT_SCALE) as i32) / 2;
+
+    text::draw_text(canvas, line, x, y, TEXT_SCALE, colour);
+}
+
+// Draws text on a dark box so it can be read over any game
+fn draw_shaded(canvas: &mut Canvas<Window>, line: &str, x: i32, y: i32) {
+    let padding = TEXT_SCALE as i32 * 2;
+
+    canvas.set_draw_color(SHADE);
+    let _ = canvas.fill_rect(Rect::new(x - padding, y - padding, text::text_width(line, TEXT_SCALE) + padding as u32 * 2, ROW_HEIGHT as u32 + padding as u32));
+
+    text::draw_text(canvas, line, x, y, TEXT_SCALE, TEXT);
+}
@@ -0,0 +1,79 @@
+use sdl2::pixels::Color;
+
+use crate::config::Config;
+
+// The config keys for each colour, in the same order as the colours in a palette
+const COLOUR_KEYS: [&str; 4] = ["background", "foreground", "foreground2", "blend"];
+
+// The colours a pixel is drawn in, indexed by which XO-CHIP planes it is set in: none, the first, the second or both.
+// Plain CHIP-8 only ever uses the first two
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Palette {
+    pub colours: [Color; 4],
+}
+
+impl Palette {
+    pub fn background(&self) -> Color {
+        self.colours[0]
+    }
+
+    pub fn foreground(&self) -> Color {
+        self.colours[1]
+    }
+
+    // Replaces any colours set in the config, leaving the rest as they are
+    pub fn apply_config(&mut self, config: &Config) {
+        for (colour, key) in self.colours.iter_mut().zip(COLOUR_KEYS) {
+            if let Some(value) = config.get_or_warn::<String>(key) {
+                match parse_colour(&value) {
+                    Ok(c) => *colour = c,
+                    Err(e) => println!("{}: {}", key, e),
+                }
+            }
+        }
+    }
+}
+
+pub struct Theme {
+    pub name: &'static str,
+    pub palette: Palette,
+}
+
+const fn rgb(c: u32) -> Color {
+    Color::RGB((c >> 16) as u8, (c >> 8) as u8, c as u8)
+}
+
+const fn theme(name: &'static str, colours: [u32; 4]) -> Theme {
+    Theme {
+        name,
+        palette: Palette {
+            colours: [rgb(colours[0]), rgb(colours[1]), rgb(colours[2]), rgb(colours[3])],
+        },
+    }
+}
+
+pub const THEMES: [Theme; 5] = [
+    theme("classic", [0x000000, 0xFFFFFF, 0xAAAAAA, 0x555555]),
+    theme("amber", [0x1A0F00, 0xFFB000, 0xB37400, 0xFFE0A0]),
+    theme("green", [0x001A06, 0x33FF66, 0x1F9E40, 0xB0FFC8]),
+    theme("lcd", [0xC7D6A5, 0x2F3B1F, 0x7E8C5C, 0x4F5C37]),
+    // The colours Octo uses by default
+    theme("octo", [0x996600, 0xFFCC00, 0xFF6600, 0x662200]),
+];
+
+pub fn find_theme(name: &str) -> Option<usize> {
+    THEMES.iter().position(|theme| theme.name.eq_ignore_ascii_case(name))
+}
+
+// Colours are written as six hex digits, with or without a leading 0x. A leading # can't be used in config files as it starts a comment
+pub fn parse_colour(s: &str) -> Result<Color, String> {
+    let digits = s.trim_start_matches('#').trim_start_matches("0x").trim_start_matches("0X");
+
+    if digits.len() != 6 {
+        return Err(format!("\"{}\" is not a colour, expected RRGGBB", s));
+    }
+
+    let value = u32::from_str_radix(digits, 16).map_err(|_| format!("\"{}\" is not a colour, expected RRGGBB", s))?;
+
+    Ok(rgb(value))
+}
@@ -0,0 +1,144 @@
+use sdl2::pixels::Color;
+
+use std::fmt;
+use std::str::FromStr;
+
+// How much of a pixel's brightness is left a frame after it is switched off, unless the config says otherwise
+pub const DEFAULT_DECAY: f32 = 0.6;
+
+// Below this a pixel is treated as fully off, so that fading ends rather than going on forever at invisible levels
+const CUTOFF: f32 = 1.0 / 255.0;
+
+// Ways of hiding the flicker from sprites being erased and redrawn every frame
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Persistence {
+    Off,
+    // Pixels lit in the previous frame are shown at half brightness
+    Blend,
+    // Pixels fade out over several frames like the phosphor on a CRT
+    Decay,
+}
+
+impl Persistence {
+    pub fn next(self) -> Self {
+        match self {
+            Persistence::Off => Persistence::Blend,
+            Persistence::Blend => Persistence::Decay,
+            Persistence::Decay => Persistence::Off,
+        }
+    }
+}
+
+impl fmt::Display for Persistence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Persistence::Off => write!(f, "off"),
+            Persistence::Blend => write!(f, "blend"),
+            Persistence::Decay => write!(f, "decay"),
+        }
+    }
+}
+
+impl FromStr for Persistence {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Persistence::Off),
+            "blend" => Ok(Persistence::Blend),
+            "decay" => Ok(Persistence::Decay),
+            _ => Err(format!("\"{}\" is not a persistence mode, expected off, blend or decay", s)),
+        }
+    }
+}
+
+// The brightness of every pixel as it is shown, built up from the frames the CHIP-8 has drawn.
+// This only affects what is shown, the CHIP-8's own video memory is left alone
+pub struct Phosphor {
+    pub mode: Persistence,
+    decay: f32,
+    width: usize,
+    height: usize,
+    intensity: Vec<f32>,
+    previous: Vec<bool>,
+}
+
+impl Phosphor {
+    pub fn new(mode: Persistence, decay: f32) -> Self {
+        Phosphor {
+            mode,
+            decay: decay.clamp(0.0, 1.0),
+            width: 0,
+            height: 0,
+            intensity: Vec::new(),
+            previous: Vec::new(),
+        }
+    }
+
+    // Clears anything left over from a previous ROM
+    pub fn clear(&mut self) {
+        self.intensity.fill(0.0);
+        self.previous.fill(false);
+    }
+
+    // Called once per frame with the current video memory, given as a list of rows of any size.
+    // Returns whether anything shown has changed
+    pub fn update<R: AsRef<[bool]>>(&mut self, video: &[R]) -> bool {
+        let height = video.len();
+        let width = video.first().map_or(0, |row| row.as_ref().len());
+        let mut changed = false;
+
+        // The resolution has changed, so nothing from before lines up any more
+        if width != self.width || height != self.height {
+            self.width = width;
+            self.height = height;
+            self.intensity = vec![0.0; width * height];
+            self.previous = vec![false; width * height];
+            changed = true;
+        }
+
+        let pixels = video.iter().flat_map(|row| row.as_ref().iter());
+
+        for ((&lit, shown), was_lit) in pixels.zip(self.intensity.iter_mut()).zip(self.previous.iter_mut()) {
+            let intensity = match self.mode {
+                _ if lit => 1.0,
+                Persistence::Off => 0.0,
+                Persistence::Blend => if *was_lit { 0.5 } else { 0.0 },
+                Persistence::Decay => {
+                    let faded = *shown * self.decay;
+
+                    if faded < CUTOFF { 0.0 } else { faded }
+                },
+            };
+
+            if intensity != *shown {
+                *shown = intensity;
+                changed = true;
+            }
+
+            *was_lit = lit;
+        }
+
+        changed
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    // One value per pixel from 0 (off) to 1 (fully lit), row by row
+    pub fn intensity(&self) -> &[f32] {
+        &self.intensity
+    }
+}
+
+// Mixes between two colours, where 0 gives the first and 1 the second
+pub fn blend(from: Color, to: Color, amount: f32) -> Color {
+    let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * amount).round() as u8;
+
+    Color::RGB(mix(from.r, to.r), mix(from.g, to.g), mix(from.b, to.b))
+}
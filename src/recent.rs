@@ -0,0 +1,56 @@
+use std::fs;
+
+use crate::loader::RomChoice;
+
+pub const RECENT_PATH: &str = "recent.txt";
+
+const MAX_RECENT: usize = 10;
+
+// The most recently played ROMs, newest first. Stored one per line, with a tab separating an archive from the file inside it
+pub struct RecentRoms {
+    path: String,
+    roms: Vec<RomChoice>,
+}
+
+impl RecentRoms {
+    // A missing or unreadable list is treated as empty, as there is nothing useful to do about it
+    pub fn load(path: &str) -> Self {
+        let roms = fs::read_to_string(path).unwrap_or_default()
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| match line.split_once('\t') {
+                Some((path, entry)) => RomChoice {
+                    path: path.to_string(),
+                    entry: Some(entry.to_string()),
+                },
+                None => RomChoice::file(line),
+            })
+            .take(MAX_RECENT)
+            .collect();
+
+        RecentRoms {
+            path: path.to_string(),
+            roms,
+        }
+    }
+
+    pub fn roms(&self) -> &[RomChoice] {
+        &self.roms
+    }
+
+    // Moves the ROM to the top of the list and saves it
+    pub fn add(&mut self, rom: &RomChoice) {
+        self.roms.retain(|r| r != rom);
+        self.roms.insert(0, rom.clone());
+        self.roms.truncate(MAX_RECENT);
+
+        let text: String = self.roms.iter().map(|r| match &r.entry {
+            Some(entry) => format!("{}\t{}\n", r.path, entry),
+            None => format!("{}\n", r.path),
+        }).collect();
+
+        if let Err(e) = fs::write(&self.path, text) {
+            println!("Unable to save recent ROMs to {}: {}", self.path, e);
+        }
+    }
+}
@@ -0,0 +1,382 @@
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, Texture, TextureCreator};
+use sdl2::video::{Window, WindowContext};
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::config::Config;
+use crate::palette::Palette;
+use crate::phosphor::{self, Phosphor};
+
+const BYTES_PER_PIXEL: usize = 3;
+
+// How dark the gaps between scanlines and grid cells are, as a fraction of the full brightness
+const SCANLINE_DARKNESS: f32 = 0.45;
+const GRID_DARKNESS: f32 = 0.3;
+
+// How much light spreads out from lit pixels
+const GLOW_STRENGTH: f32 = 0.35;
+
+// How strongly the picture bulges, as if drawn on a curved CRT
+const CURVATURE: f32 = 0.08;
+
+// Software post-processing, applied by the CPU so it works without any GPU support
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub struct Effects {
+    pub enabled: bool,
+    pub scanlines: bool,
+    pub pixel_grid: bool,
+    pub glow: bool,
+    pub curvature: bool,
+}
+
+impl Effects {
+    // Each effect is switched on by its own setting. If none are set, scanlines and glow are used when effects are turned on at runtime
+    pub fn from_config(config: &Config) -> Self {
+        let mut effects = Effects {
+            enabled: false,
+            scanlines: config.get_or_warn("scanlines").unwrap_or(false),
+            pixel_grid: config.get_or_warn("pixel_grid").unwrap_or(false),
+            glow: config.get_or_warn("glow").unwrap_or(false),
+            curvature: config.get_or_warn("curvature").unwrap_or(false),
+        };
+
+        effects.enabled = effects.scanlines || effects.pixel_grid || effects.glow || effects.curvature;
+
+        if !effects.enabled {
+            effects.scanlines = true;
+            effects.glow = true;
+        }
+
+        effects
+    }
+}
+
+// How the screen is fitted into the window. Any space left over is filled with black
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Scaling {
+    // The largest whole number multiple of the resolution that fits, so every pixel is the same size
+    Integer,
+    // As large as fits while keeping pixels square
+    Aspect,
+    // Fills the whole window
+    Stretch,
+}
+
+impl Scaling {
+    pub fn next(self) -> Self {
+        match self {
+            Scaling::Integer => Scaling::Aspect,
+            Scaling::Aspect => Scaling::Stretch,
+            Scaling::Stretch => Scaling::Integer,
+        }
+    }
+
+    // Where a width x height picture goes in a window of the given size
+    fn fit(self, width: u32, height: u32, window_width: u32, window_height: u32) -> Rect {
+        let (w, h) = match self {
+            Scaling::Integer => {
+                let scale = (window_width / width).min(window_height / height).max(1);
+
+                (width * scale, height * scale)
+            },
+            Scaling::Aspect => {
+                let scale = (window_width as f32 / width as f32).min(window_height as f32 / height as f32);
+
+                (((width as f32 * scale) as u32).max(1), ((height as f32 * scale) as u32).max(1))
+            },
+            Scaling::Stretch => (window_width.max(1), window_height.max(1)),
+        };
+
+        Rect::new((window_width as i32 - w as i32) / 2, (window_height as i32 - h as i32) / 2, w, h)
+    }
+}
+
+impl fmt::Display for Scaling {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Scaling::Integer => write!(f, "integer"),
+            Scaling::Aspect => write!(f, "aspect"),
+            Scaling::Stretch => write!(f, "stretch"),
+        }
+    }
+}
+
+impl FromStr for Scaling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "integer" => Ok(Scaling::Integer),
+            "aspect" => Ok(Scaling::Aspect),
+            "stretch" => Ok(Scaling::Stretch),
+            _ => Err(format!("\"{}\" is not a scaling mode, expected integer, aspect or stretch", s)),
+        }
+    }
+}
+
+// What one output pixel shows with effects on. It only depends on the output size, the CHIP-8's resolution and which
+// effects are on, so the whole map is kept until one of those changes instead of being worked out every frame
+#[derive(Clone, Copy)]
+struct EffectPixel {
+    // The CHIP-8 pixel shown here, or BEZEL outside of the curved screen
+    cell: u16,
+    // How much scanlines and the pixel grid darken this point
+    brightness: f32,
+    // The blurred pixel the glow is read from, and how far it is towards the next one across and down
+    glow_x: u8,
+    glow_y: u8,
+    glow_tx: f32,
+    glow_ty: f32,
+}
+
+impl EffectPixel {
+    const BEZEL: u16 = u16::MAX;
+}
+
+// Converts the screen into a streaming texture and copies it to the canvas in one go.
+// Without effects the texture is the CHIP-8's own resolution and the GPU scales it up. With effects
+// it has to be drawn at the size it is shown, so the effects have pixels to work with
+pub struct Renderer<'a> {
+    pub effects: Effects,
+    pub scaling: Scaling,
+    texture_creator: &'a TextureCreator<WindowContext>,
+    texture: Option<Texture<'a>>,
+    texture_size: (u32, u32),
+    pixels: Vec<u8>,
+    glow: Vec<f32>,
+    colours: Vec<Color>,
+    effect_map: Vec<EffectPixel>,
+    effect_key: Option<(Effects, (u32, u32), usize, usize)>,
+}
+
+impl<'a> Renderer<'a> {
+    pub fn new(texture_creator: &'a TextureCreator<WindowContext>, effects: Effects, scaling: Scaling) -> Self {
+        Renderer {
+            effects,
+            scaling,
+            texture_creator,
+            texture: None,
+            texture_size: (0, 0),
+            pixels: Vec::new(),
+            glow: Vec::new(),
+            colours: Vec::new(),
+            effect_map: Vec::new(),
+            effect_key: None,
+        }
+    }
+
+    pub fn draw(&mut self, canvas: &mut Canvas<Window>, phosphor: &Phosphor, palette: &Palette) {
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        canvas.clear();
+
+        let (window_width, window_height) = canvas.output_size().unwrap_or((0, 0));
+        let (width, height) = (phosphor.width() as u32, phosphor.height() as u32);
+
+        if width == 0 || height == 0 || window_width == 0 || window_height == 0 {
+            return;
+        }
+
+        let dest = self.scaling.fit(width, height, window_width, window_height);
+
+        let (texture_width, texture_height) = if self.effects.enabled {
+            (dest.width(), dest.height())
+        }
+        else {
+            (width, height)
+        };
+
+        if self.texture.is_none() || self.texture_size != (texture_width, texture_height) {
+            match self.texture_creator.create_texture_streaming(PixelFormatEnum::RGB24, texture_width, texture_height) {
+                Ok(texture) => self.texture = Some(texture),
+                Err(e) => {
+                    println!("Unable to create the screen texture: {}", e);
+                    return;
+                },
+            }
+
+            self.texture_size = (texture_width, texture_height);
+            self.pixels = vec![0; texture_width as usize * texture_height as usize * BYTES_PER_PIXEL];
+        }
+
+        if self.effects.enabled {
+            self.render_effects(phosphor, palette);
+        }
+        else {
+            self.render_plain(phosphor, palette);
+        }
+
+        if let Some(texture) = &mut self.texture {
+            let _ = texture.update(None, &self.pixels, texture_width as usize * BYTES_PER_PIXEL);
+            let _ = canvas.copy(texture, None, dest);
+        }
+    }
+
+    // One texture pixel per CHIP-8 pixel
+    fn render_plain(&mut self, phosphor: &Phosphor, palette: &Palette) {
+        for (pixel, &intensity) in self.pixels.chunks_exact_mut(BYTES_PER_PIXEL).zip(phosphor.intensity()) {
+            let colour = phosphor::blend(palette.background(), palette.foreground(), intensity);
+
+            pixel.copy_from_slice(&[colour.r, colour.g, colour.b]);
+        }
+    }
+
+    fn render_effects(&mut self, phosphor: &Phosphor, palette: &Palette) {
+        let effects = self.effects;
+        let (columns, rows) = (phosphor.width(), phosphor.height());
+        let key = (effects, self.texture_size, columns, rows);
+
+        if self.effect_key != Some(key) {
+            self.build_effect_map(columns, rows);
+            self.effect_key = Some(key);
+        }
+
+        if effects.glow {
+            self.blur(phosphor);
+        }
+
+        let foreground = palette.foreground();
+
+        self.colours.clear();
+        self.colours.extend(phosphor.intensity().iter().map(|&intensity| phosphor::blend(palette.background(), foreground, intensity)));
+
+        for (pixel, point) in self.pixels.chunks_exact_mut(BYTES_PER_PIXEL).zip(&self.effect_map) {
+            // Outside of the curved screen is left black, like the bezel of a CRT
+            if point.cell == EffectPixel::BEZEL {
+                pixel.fill(0);
+                continue;
+            }
+
+            let colour = self.colours[point.cell as usize];
+            let glow = if effects.glow { sample_glow(&self.glow, point, columns, rows) * GLOW_STRENGTH } else { 0.0 };
+
+            let channel = |base: u8, lit: u8| (base as f32 * point.brightness + lit as f32 * glow).round().clamp(0.0, 255.0) as u8;
+
+            pixel[0] = channel(colour.r, foreground.r);
+            pixel[1] = channel(colour.g, foreground.g);
+            pixel[2] = channel(colour.b, foreground.b);
+        }
+    }
+
+    // Works out where each output pixel reads from and how dark it is, for the current texture size and effects
+    fn build_effect_map(&mut self, columns: usize, rows: usize) {
+        let effects = self.effects;
+        let (width, height) = (self.texture_size.0 as usize, self.texture_size.1 as usize);
+
+        let cell_width = width as f32 / columns as f32;
+        let cell_height = height as f32 / rows as f32;
+
+        self.effect_map.clear();
+        self.effect_map.reserve(width * height);
+
+        for y in 0..height {
+            for x in 0..width {
+                let (sx, sy) = if effects.curvature {
+                    curve(x, y, width, height)
+                }
+                else {
+                    (x as f32 + 0.5, y as f32 + 0.5)
+                };
+
+                if sx < 0.0 || sy < 0.0 || sx >= width as f32 || sy >= height as f32 {
+                    self.effect_map.push(EffectPixel {
+                        cell: EffectPixel::BEZEL,
+                        brightness: 0.0,
+                        glow_x: 0,
+                        glow_y: 0,
+                        glow_tx: 0.0,
+                        glow_ty: 0.0,
+                    });
+                    continue;
+                }
+
+                let col = ((sx / cell_width) as usize).min(columns - 1);
+                let row = ((sy / cell_height) as usize).min(rows - 1);
+
+                // Where in its cell this point is, from 0 to 1
+                let fx = sx / cell_width - col as f32;
+                let fy = sy / cell_height - row as f32;
+
+                let mut brightness = 1.0;
+
+                if effects.scanlines && fy >= 0.5 {
+                    brightness -= SCANLINE_DARKNESS;
+                }
+
+                if effects.pixel_grid && (fx < 1.0 / cell_width || fy < 1.0 / cell_height) {
+                    brightness -= GRID_DARKNESS;
+                }
+
+                // The glow is read between pixel centres, so it is smooth rather than blocky
+                let gx = (sx / cell_width - 0.5).clamp(0.0, (columns - 1) as f32);
+                let gy = (sy / cell_height - 0.5).clamp(0.0, (rows - 1) as f32);
+
+                self.effect_map.push(EffectPixel {
+                    cell: (row * columns + col) as u16,
+                    brightness,
+                    glow_x: gx as u8,
+                    glow_y: gy as u8,
+                    glow_tx: gx.fract(),
+                    glow_ty: gy.fract(),
+                });
+            }
+        }
+    }
+
+    // Spreads each pixel's brightness over its neighbours with a 3x3 blur, at the CHIP-8's own resolution
+    fn blur(&mut self, phosphor: &Phosphor) {
+        const WEIGHTS: [f32; 3] = [0.25, 0.5, 0.25];
+
+        let intensity = phosphor.intensity();
+        let (columns, rows) = (phosphor.width(), phosphor.height());
+
+        self.glow.resize(columns * rows, 0.0);
+
+        for y in 0..rows {
+            for x in 0..columns {
+                let mut total = 0.0;
+
+                for (dy, wy) in WEIGHTS.iter().enumerate() {
+                    for (dx, wx) in WEIGHTS.iter().enumerate() {
+                        let sy = (y + dy).checked_sub(1).filter(|&sy| sy < rows);
+                        let sx = (x + dx).checked_sub(1).filter(|&sx| sx < columns);
+
+                        if let (Some(sx), Some(sy)) = (sx, sy) {
+                            total += intensity[sy * columns + sx] * wx * wy;
+                        }
+                    }
+                }
+
+                self.glow[y * columns + x] = total;
+            }
+        }
+    }
+}
+
+// Reads the blurred brightness at a point of the effect map, blending the four pixels around it
+fn sample_glow(glow: &[f32], point: &EffectPixel, columns: usize, rows: usize) -> f32 {
+    let x0 = point.glow_x as usize;
+    let y0 = point.glow_y as usize;
+    let x1 = (x0 + 1).min(columns - 1);
+    let y1 = (y0 + 1).min(rows - 1);
+
+    let at = |x: usize, y: usize| glow[y * columns + x];
+
+    let top = at(x0, y0) + (at(x1, y0) - at(x0, y0)) * point.glow_tx;
+    let bottom = at(x0, y1) + (at(x1, y1) - at(x0, y1)) * point.glow_tx;
+
+    top + (bottom - top) * point.glow_ty
+}
+
+// Maps a point on the screen to the point of the flat picture that ends up there once it is bulged outwards
+fn curve(x: usize, y: usize, width: usize, height: usize) -> (f32, f32) {
+    let u = (x as f32 + 0.5) / width as f32 * 2.0 - 1.0;
+    let v = (y as f32 + 0.5) / height as f32 * 2.0 - 1.0;
+
+    let cu = u * (1.0 + CURVATURE * v * v);
+    let cv = v * (1.0 + CURVATURE * u * u);
+
+    ((cu + 1.0) / 2.0 * width as f32, (cv + 1.0) / 2.0 * height as f32)
+}
@@ -0,0 +1,53 @@
+// Source of the random bytes used by CXNN. Implement this to drive the CPU from something other
+// than the built in generator, e.g. a fixed sequence in a test or a stream shared over a network
+pub trait RandomSource {
+    fn next_byte(&mut self) -> u8;
+
+    // Used by save states. A source that can't be saved can leave these alone, and will carry on
+    // from wherever it is when a state is loaded
+    fn state(&self) -> u64 {
+        0
+    }
+
+    fn set_state(&mut self, _state: u64) {}
+}
+
+// xorshift64*, which is fast, has a single u64 of state and produces the same sequence on every platform
+// and version of the emulator, unlike generators from the rand crate
+pub struct SeededRng {
+    state: u64,
+}
+
+impl SeededRng {
+    pub fn new(seed: u64) -> Self {
+        // xorshift gets stuck at zero forever, so mix the seed with splitmix64 to get a non-zero state
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+
+        SeededRng {
+            state: if z == 0 { 1 } else { z },
+        }
+    }
+}
+
+impl RandomSource for SeededRng {
+    fn next_byte(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+
+    fn state(&self) -> u64 {
+        self.state
+    }
+
+    fn set_state(&mut self, state: u64) {
+        if state != 0 {
+            self.state = state;
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use std::time::{Duration, Instant};
+
+// The most ticks that will be run at once to catch up. Beyond this the emulator has been held up (by a
+// dialog, the window being dragged or a slow machine) and the missed time is skipped rather than rushed through
+const MAX_CATCH_UP: u32 = 5;
+
+// Keeps a fixed rate of ticks against the real clock, however often the main loop happens to run.
+// Ticks that come due while the loop is busy are run late rather than dropped, so the rate doesn't drift
+pub struct Scheduler {
+    rate_period: Duration,
+    period: Duration,
+    next: Instant,
+    // Runs as many ticks as fit in each display frame instead of keeping to the rate
+    uncapped: bool,
+    pass_start: Instant,
+}
+
+impl Scheduler {
+    pub fn new(rate: u32) -> Self {
+        let period = Duration::from_secs(1) / rate;
+
+        Scheduler {
+            rate_period: period,
+            period,
+            next: Instant::now(),
+            uncapped: false,
+            pass_start: Instant::now(),
+        }
+    }
+
+    // Runs ticks at a multiple of the normal rate, e.g. 0.5 for half speed
+    pub fn set_speed(&mut self, speed: f32) {
+        self.period = self.rate_period.div_f32(speed);
+    }
+
+    pub fn set_uncapped(&mut self, uncapped: bool) {
+        self.uncapped = uncapped;
+        self.next = Instant::now();
+    }
+
+    // How many ticks have come due since the last call. When uncapped there is no limit, and the
+    // caller should keep going until out_of_time says to stop
+    pub fn due(&mut self) -> u32 {
+        let now = Instant::now();
+        let mut ticks = 0;
+
+        if self.uncapped {
+            self.pass_start = now;
+            self.next = now;
+            return u32::MAX;
+        }
+
+        while self.next <= now {
+            if ticks == MAX_CATCH_UP {
+                self.next = now + self.period;
+                break;
+            }
+
+            self.next += self.period;
+            ticks += 1;
+        }
+
+        ticks
+    }
+
+    // Starts counting again from now, for when the ticks have been stopped on purpose and shouldn't be caught up on
+    pub fn reset(&mut self) {
+        self.next = Instant::now();
+    }
+
+    // Whether an uncapped pass has taken as long as a tick at the normal rate would, and should stop so the screen can be drawn
+    pub fn out_of_time(&self) -> bool {
+        self.uncapped && self.pass_start.elapsed() >= self.rate_period
+    }
+
+    // Sleeps until the next tick is due
+    pub fn wait(&self) {
+        let now = Instant::now();
+
+        if self.next > now {
+            std::thread::sleep(self.next - now);
+        }
+    }
+}
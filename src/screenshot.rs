@@ -0,0 +1,72 @@
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::palette::Palette;
+
+pub const SCREENSHOT_FOLDER: &str = "screenshots";
+
+// Writes video memory, given as a list of rows of any size, to a PNG in the palette's colours.
+// Each CHIP-8 pixel becomes a scale x scale block, so a scale of 1 gives the native resolution
+pub fn save_png<R: AsRef<[bool]>>(path: &Path, video: &[R], palette: &Palette, scale: u32) -> Result<(), String> {
+    let scale = scale.max(1) as usize;
+    let height = video.len();
+    let width = video.first().map_or(0, |row| row.as_ref().len());
+
+    if width == 0 || height == 0 {
+        return Err("The screen is empty".to_string());
+    }
+
+    let mut data = Vec::with_capacity(width * scale * height * scale * 3);
+
+    for row in video {
+        let mut line = Vec::with_capacity(width * scale * 3);
+
+        for &lit in row.as_ref() {
+            let colour = if lit { palette.foreground() } else { palette.background() };
+
+            for _ in 0..scale {
+                line.extend_from_slice(&[colour.r, colour.g, colour.b]);
+            }
+        }
+
+        for _ in 0..scale {
+            data.extend_from_slice(&line);
+        }
+    }
+
+    let file = File::create(path).map_err(|e| format!("Unable to create {}: {}", path.display(), e))?;
+
+    let mut encoder = png::Encoder::new(BufWriter::new(file), (width * scale) as u32, (height * scale) as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().map_err(|e| format!("Unable to write {}: {}", path.display(), e))?;
+    writer.write_image_data(&data).map_err(|e| format!("Unable to write {}: {}", path.display(), e))
+}
+
+// Saves the screen twice, once at its native resolution and once scaled up, named after the ROM and the time.
+// Returns the path of the scaled screenshot
+pub fn capture<R: AsRef<[bool]>>(name: &str, video: &[R], palette: &Palette, scale: u32) -> Result<PathBuf, String> {
+    fs::create_dir_all(SCREENSHOT_FOLDER).map_err(|e| format!("Unable to create {}: {}", SCREENSHOT_FOLDER, e))?;
+
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |t| t.as_secs());
+    let mut stem = format!("{}-{}", name, time);
+
+    // Several screenshots in the same second get numbered rather than overwriting each other
+    let mut count = 1;
+
+    while Path::new(SCREENSHOT_FOLDER).join(format!("{}.png", stem)).exists() {
+        count += 1;
+        stem = format!("{}-{}-{}", name, time, count);
+    }
+
+    let native = Path::new(SCREENSHOT_FOLDER).join(format!("{}.png", stem));
+    let scaled = Path::new(SCREENSHOT_FOLDER).join(format!("{}-x{}.png", stem, scale));
+
+    save_png(&native, video, palette, 1)?;
+    save_png(&scaled, video, palette, scale)?;
+
+    Ok(scaled)
+}
@@ -0,0 +1,254 @@
+use std::fs;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+
+use crate::chip8::{Chip8, KeyWait, ResetKind, DEFAULT_LOAD_ADDRESS};
+use crate::config::{self, Config};
+use crate::loader::{self, RomChoice};
+use crate::movie::{self, MovieHeader};
+use crate::palette::{self, Palette};
+use crate::rng::SeededRng;
+use crate::timing::{self, TimingMode};
+use crate::watcher::FileWatcher;
+use crate::{parse_address, Options, CYCLES_PER_TIMER_TICK};
+
+const STATE_FOLDER: &str = "states";
+
+// Settings for a single ROM, in the same format as the main config, live in here as "<rom file>.cfg"
+const PROFILE_FOLDER: &str = "profiles";
+
+// A ROM that is loaded and running, along with the settings it was started with
+pub struct Session {
+    pub chip8: Chip8,
+    pub rom: RomChoice,
+    pub header: MovieHeader,
+    pub palette: Palette,
+    // Which of the built in themes the palette is, if it hasn't been changed by the config or ROM
+    theme: Option<usize>,
+    load_address: u16,
+    watcher: Option<FileWatcher>,
+    keep_registers: bool,
+}
+
+impl Session {
+    // Loads the ROM and sets up the machine for it. When a movie is about to be played its header
+    // decides the timing and quirks, so that the run matches the recording
+    pub fn start(rom: &RomChoice, options: &Options, config: &Config, movie: Option<&MovieHeader>, seed: u64) -> Result<Session, String> {
+        let bytes = loader::load(rom)?;
+        let profile = load_profile(rom);
+
+        // The ROM's profile is more specific than the config, and both can be overridden from the command line
+        let timing = movie.map(|header| header.timing)
+            .or(options.timing)
+            .or_else(|| profile.get_or_warn("timing"))
+            .or_else(|| config.get_or_warn("timing"))
+            .or_else(|| config.get_or_warn::<NonZeroU32>("cycles_per_tick").map(|cycles| TimingMode::Fixed(cycles.get())))
+            .unwrap_or(TimingMode::Fixed(CYCLES_PER_TIMER_TICK));
+
+        let display_wait = match movie {
+            Some(header) => header.display_wait,
+            None => options.display_wait || profile.get_or_warn("display_wait")
+                .or_else(|| config.get_or_warn("display_wait"))
+                .unwrap_or(false),
+        };
+
+        let key_wait = movie.map(|header| header.key_wait)
+            .or_else(|| profile.get_or_warn("key_wait"))
+            .or_else(|| config.get_or_warn("key_wait"))
+            .unwrap_or(KeyWait::Release);
+
+        let (palette, theme) = choose_palette(&profile, config);
+
+        let load_address = match movie.map(|header| header.load_address).or(options.load_address) {
+            Some(address) => address,
+            None => match config.get_or_warn::<String>("load_address") {
+                Some(s) => parse_address(&s).unwrap_or_else(|e| {
+                    println!("{}: {}", config::CONFIG_PATH, e);
+                    DEFAULT_LOAD_ADDRESS
+                }),
+                None => DEFAULT_LOAD_ADDRESS,
+            },
+        };
+
+        println!("Timing: {}", timing);
+
+        // Initialises the CHIP-8 and loads the ROM
+        let mut chip8 = Chip8::init(Box::new(SeededRng::new(seed)), timing);
+        chip8.set_display_wait(display_wait);
+        chip8.set_key_wait(key_wait);
+        chip8.load_rom(&bytes, load_address)?;
+
+        // Reloads the ROM whenever it is rebuilt, so changes can be tried out without restarting
+        let watcher = if options.watch || config.get_or_warn("watch").unwrap_or(false) {
+            Some(FileWatcher::new(&rom.path))
+        }
+        else {
+            None
+        };
+
+        Ok(Session {
+            chip8,
+            rom: rom.clone(),
+            header: MovieHeader {
+                rom_hash: movie::rom_hash(&bytes),
+                timing,
+                load_address,
+                display_wait,
+                key_wait,
+                seed: Some(seed),
+            },
+            palette,
+            theme,
+            load_address,
+            watcher,
+            keep_registers: options.keep_registers || config.get_or_warn("watch_keep_registers").unwrap_or(false),
+        })
+    }
+
+    // Reloads the ROM if it has changed on disk since it was last loaded. Returns whether it was reloaded
+    pub fn poll_reload(&mut self) -> bool {
+        if !self.watcher.as_mut().is_some_and(|w| w.poll()) {
+            return false;
+        }
+
+        match loader::load(&self.rom) {
+            // The file is probably still being written, it will be picked up once the write finishes
+            Ok(reloaded) if reloaded.is_empty() => {},
+            Ok(reloaded) => {
+                let snapshot = self.chip8.snapshot_registers();
+
+                match self.chip8.load_rom(&reloaded, self.load_address) {
+                    Ok(()) => {
+                        self.chip8.reset(ResetKind::Hard);
+
+                        if self.keep_registers {
+                            self.chip8.restore_registers(&snapshot);
+                        }
+
+                        self.header.rom_hash = movie::rom_hash(&reloaded);
+
+                        println!("Reloaded {}", self.rom.path);
+
+                        return true;
+                    },
+                    Err(e) => println!("Unable to reload {}: {}", self.rom.path, e),
+                }
+            },
+            Err(e) => println!("Unable to reload {}: {}", self.rom.path, e),
+        }
+
+        false
+    }
+
+    // Each ROM gets its own set of numbered slots
+    fn state_path(&self, slot: u32) -> PathBuf {
+        Path::new(STATE_FOLDER).join(format!("{}.{}.state", self.rom.file_name_stem(), slot))
+    }
+
+    // Cycles through the built in themes, returning the name of the new one
+    pub fn next_theme(&mut self) -> &'static str {
+        let next = self.theme.map_or(0, |theme| (theme + 1) % palette::THEMES.len());
+
+        self.theme = Some(next);
+        self.palette = palette::THEMES[next].palette;
+
+        palette::THEMES[next].name
+    }
+
+    pub fn theme_name(&self) -> &'static str {
+        self.theme.map_or("custom", |theme| palette::THEMES[theme].name)
+    }
+
+    // Speeds the CPU up or down by a number of steps and remembers the new speed in the ROM's profile.
+    // Returns the new instructions per frame
+    pub fn adjust_speed(&mut self, steps: i32) -> Result<u32, String> {
+        let current = match self.chip8.timing() {
+            TimingMode::Fixed(instructions_per_frame) => instructions_per_frame,
+            TimingMode::CosmacVip => return Err("The speed is set by the VIP timing mode".to_string()),
+        };
+
+        let timing = TimingMode::Fixed(timing::step_instructions_per_frame(current, steps));
+
+        self.chip8.set_timing(timing);
+        self.header.timing = timing;
+
+        // Not being able to save it only means it has to be set again next time
+        if let Err(e) = Config::store(&profile_path(&self.rom), "timing", &timing.to_string()) {
+            println!("{}", e);
+        }
+
+        Ok(timing.frame_budget())
+    }
+
+    pub fn save_state(&self, slot: u32) -> Result<(), String> {
+        let path = self.state_path(slot);
+
+        fs::create_dir_all(STATE_FOLDER).map_err(|e| format!("Unable to create {}: {}", STATE_FOLDER, e))?;
+        fs::write(&path, self.chip8.save_state()).map_err(|e| format!("Unable to write {}: {}", path.display(), e))
+    }
+
+    pub fn load_state(&mut self, slot: u32) -> Result<(), String> {
+        let path = self.state_path(slot);
+
+        if !path.exists() {
+            return Err(format!("Slot {} is empty", slot));
+        }
+
+        let state = fs::read(&path).map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+
+        self.chip8.load_state(&state)?;
+
+        // The state brings back the timing and quirks it was saved with
+        self.header.timing = self.chip8.timing();
+        self.header.display_wait = self.chip8.display_wait();
+        self.header.key_wait = self.chip8.key_wait();
+
+        Ok(())
+    }
+}
+
+fn profile_path(rom: &RomChoice) -> PathBuf {
+    Path::new(PROFILE_FOLDER).join(format!("{}.cfg", rom.file_name_stem()))
+}
+
+fn load_profile(rom: &RomChoice) -> Config {
+    let path = profile_path(rom);
+
+    if !path.exists() {
+        return Config::default();
+    }
+
+    Config::load(&path.to_string_lossy()).unwrap_or_else(|e| {
+        println!("{}. Ignoring the ROM's profile", e);
+        Config::default()
+    })
+}
+
+// Starts from the theme in the config, then lets colours from the config and then the ROM's profile replace it
+fn choose_palette(profile: &Config, config: &Config) -> (Palette, Option<usize>) {
+    let find = |config: &Config| config.get_or_warn::<String>("theme").and_then(|name| {
+        let theme = palette::find_theme(&name);
+
+        if theme.is_none() {
+            println!("Unknown theme \"{}\"", name);
+        }
+
+        theme
+    });
+
+    let mut theme = find(config).unwrap_or(0);
+    let mut palette = palette::THEMES[theme].palette;
+
+    palette.apply_config(config);
+
+    if let Some(profile_theme) = find(profile) {
+        theme = profile_theme;
+        palette = palette::THEMES[theme].palette;
+    }
+
+    palette.apply_config(profile);
+
+    let is_theme = palette == palette::THEMES[theme].palette;
+
+    (palette, if is_theme { Some(theme) } else { None })
+}
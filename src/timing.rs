@@ -0,0 +1,132 @@
+use std::fmt;
+use std::str::FromStr;
+
+// The VIP's 1802 runs at 1.76 MHz with 8 clocks per machine cycle, giving 3668 machine cycles per 60 Hz frame
+const VIP_CYCLES_PER_FRAME: u32 = 3668;
+
+// The display interrupt and the DMA that feeds the 1861 video chip take up roughly half of every frame
+const VIP_INTERRUPT_CYCLES: u32 = 1832;
+
+// Cycles spent by the interpreter's main loop fetching an instruction and jumping to its routine
+const VIP_FETCH_CYCLES: u32 = 40;
+
+// The instructions per frame the speed can be stepped through while running. Games want anything from a handful
+// to well over a thousand, so the steps grow with the speed
+const INSTRUCTIONS_PER_FRAME_STEPS: [u32; 21] = [
+    1, 2, 3, 5, 7, 8, 10, 12, 15, 20, 30, 50, 75, 100, 150, 200, 300, 500, 750, 1000, 2000,
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TimingMode {
+    // Every instruction costs the same, with a set number run each frame
+    Fixed(u32),
+    // Each instruction costs what its routine took in the original COSMAC VIP interpreter
+    CosmacVip,
+}
+
+impl TimingMode {
+    // The amount of cycles available to the interpreter in one frame. In fixed mode an instruction costs one cycle
+    pub fn frame_budget(&self) -> u32 {
+        match *self {
+            TimingMode::Fixed(instructions_per_frame) => instructions_per_frame,
+            TimingMode::CosmacVip => VIP_CYCLES_PER_FRAME - VIP_INTERRUPT_CYCLES,
+        }
+    }
+}
+
+// Moves the given number of steps up or down from the current instructions per frame, which doesn't have to be one of the steps
+pub fn step_instructions_per_frame(current: u32, steps: i32) -> u32 {
+    let mut value = current;
+
+    for _ in 0..steps.unsigned_abs() {
+        value = if steps > 0 {
+            INSTRUCTIONS_PER_FRAME_STEPS.iter().copied().find(|&step| step > value).unwrap_or(value)
+        }
+        else {
+            INSTRUCTIONS_PER_FRAME_STEPS.iter().copied().rev().find(|&step| step < value).unwrap_or(value)
+        };
+    }
+
+    value
+}
+
+impl fmt::Display for TimingMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TimingMode::Fixed(instructions_per_frame) => write!(f, "fixed {}", instructions_per_frame),
+            TimingMode::CosmacVip => write!(f, "vip"),
+        }
+    }
+}
+
+// Parses "vip" or "fixed <instructions per frame>"
+impl FromStr for TimingMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+
+        match fields.as_slice() {
+            ["vip"] => Ok(TimingMode::CosmacVip),
+            ["fixed", ipf] => match ipf.parse() {
+                Ok(ipf) if ipf > 0 => Ok(TimingMode::Fixed(ipf)),
+                _ => Err(format!("\"{}\" is not a valid amount of instructions per frame", ipf)),
+            },
+            _ => Err(format!("unknown timing mode \"{}\"", s)),
+        }
+    }
+}
+
+// Approximate machine cycles taken by the VIP interpreter to run an instruction, not counting the time DXYN spends
+// waiting for the display interrupt. `vx` is the value of Vx when the instruction ran and `skipped` is whether
+// a skip instruction skipped
+pub fn vip_cycles(op: u16, vx: u8, skipped: bool) -> u32 {
+    let nibbles = (
+        (op & 0xF000) >> 12,
+        (op & 0x0F00) >> 8,
+        (op & 0x00F0) >> 4,
+        (op & 0x000F),
+    );
+
+    let x = nibbles.1 as u32;
+    let n = nibbles.3 as u32;
+    let skip = if skipped { 4 } else { 0 };
+
+    let cycles = match nibbles {
+        // CLS clears the 256 bytes of display memory one at a time
+        (0x0, 0x0, 0xE, 0x0) => 24 + 256 * 3,
+        (0x0, 0x0, 0xE, 0xE) => 10,
+        (0x1, _, _, _) => 12,
+        (0x2, _, _, _) => 26,
+        (0x3, _, _, _) | (0x4, _, _, _) => 10 + skip,
+        (0x5, _, _, _) | (0x9, _, _, _) => 14 + skip,
+        (0x6, _, _, _) => 6,
+        (0x7, _, _, _) => 10,
+        // The ALU instructions are run by writing the operation into RAM and executing it
+        (0x8, _, _, _) => 44,
+        (0xA, _, _, _) => 12,
+        (0xB, _, _, _) => 22,
+        (0xC, _, _, _) => 36,
+        // Each sprite row is shifted into position bit by bit and, unless it is byte aligned, spans two bytes of display memory
+        (0xD, _, _, _) => {
+            let shift = (vx % 8) as u32;
+            let row = if shift == 0 { 34 } else { 46 + shift * 4 };
+
+            26 + n * row
+        },
+        (0xE, _, _, _) => 14 + skip,
+        (0xF, _, 0x0, 0x7) | (0xF, _, 0x1, 0x5) | (0xF, _, 0x1, 0x8) => 10,
+        (0xF, _, 0x0, 0xA) => 20,
+        (0xF, _, 0x1, 0xE) | (0xF, _, 0x2, 0x9) => 16,
+        // BCD is done by repeated subtraction, so it takes longer the larger each digit is
+        (0xF, _, 0x3, 0x3) => {
+            let digits = (vx / 100) as u32 + ((vx / 10) % 10) as u32 + (vx % 10) as u32;
+
+            84 + digits * 16
+        },
+        (0xF, _, 0x5, 0x5) | (0xF, _, 0x6, 0x5) => 14 + (x + 1) * 14,
+        _ => 0,
+    };
+
+    VIP_FETCH_CYCLES + cycles
+}
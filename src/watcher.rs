@@ -0,0 +1,48 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
+// How often the file's modification time is checked, so the disk isn't hit every frame
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// Watches a file for changes by polling its modification time
+pub struct FileWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    last_poll: Instant,
+}
+
+impl FileWatcher {
+    pub fn new(path: &str) -> Self {
+        let path = PathBuf::from(path);
+        let modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+
+        FileWatcher {
+            path,
+            modified,
+            last_poll: Instant::now(),
+        }
+    }
+
+    // Returns true once each time the file has been modified since it was last checked
+    pub fn poll(&mut self) -> bool {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return false;
+        }
+
+        self.last_poll = Instant::now();
+
+        // The file can briefly disappear while a build tool replaces it, in which case try again next time
+        let modified = match fs::metadata(&self.path).and_then(|m| m.modified()) {
+            Ok(modified) => modified,
+            Err(_) => return false,
+        };
+
+        if Some(modified) == self.modified {
+            return false;
+        }
+
+        self.modified = Some(modified);
+        true
+    }
+}
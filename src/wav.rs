@@ -0,0 +1,69 @@
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+
+const HEADER_SIZE: u32 = 44;
+
+// Writes mono 16 bit PCM audio. The sizes in the header are filled in by finish, once the length is known
+pub struct WavWriter {
+    file: BufWriter<File>,
+    path: String,
+    samples: u32,
+}
+
+impl WavWriter {
+    pub fn create(path: &str, sample_rate: u32) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| format!("Unable to create {}: {}", path, e))?;
+
+        let mut wav = WavWriter {
+            file: BufWriter::new(file),
+            path: path.to_string(),
+            samples: 0,
+        };
+
+        let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(b"WAVEfmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&sample_rate.to_le_bytes());
+        header.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+        header.extend_from_slice(&2u16.to_le_bytes());
+        header.extend_from_slice(&16u16.to_le_bytes());
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&0u32.to_le_bytes());
+
+        wav.file.write_all(&header).map_err(|e| wav.error(e))?;
+
+        Ok(wav)
+    }
+
+    fn error(&self, e: std::io::Error) -> String {
+        format!("Unable to write {}: {}", self.path, e)
+    }
+
+    // Samples are from -1 to 1
+    pub fn write(&mut self, samples: &[f32]) -> Result<(), String> {
+        let bytes: Vec<u8> = samples.iter()
+            .flat_map(|&s| ((s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).to_le_bytes())
+            .collect();
+
+        self.file.write_all(&bytes).map_err(|e| self.error(e))?;
+        self.samples += samples.len() as u32;
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<(), String> {
+        let data_size = self.samples * 2;
+
+        let result = self.file.seek(SeekFrom::Start(4))
+            .and_then(|_| self.file.write_all(&(HEADER_SIZE - 8 + data_size).to_le_bytes()))
+            .and_then(|_| self.file.seek(SeekFrom::Start(HEADER_SIZE as u64 - 4)))
+            .and_then(|_| self.file.write_all(&data_size.to_le_bytes()))
+            .and_then(|_| self.file.flush());
+
+        result.map_err(|e| self.error(e))
+    }
+}